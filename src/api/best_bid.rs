@@ -0,0 +1,25 @@
+//! The best bid we stored, per slot, parent hash and proposer.
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+
+use crate::{best_bid, bid_index::Bid, server::AppState};
+
+use super::ApiError;
+
+pub async fn get_best_bid(
+    State(state): State<AppState>,
+    Path((slot, parent_hash, proposer_pubkey)): Path<(u64, String, String)>,
+) -> Result<Json<Bid>, ApiError> {
+    let parent_hash = parent_hash.to_lowercase();
+    let proposer_pubkey = proposer_pubkey.to_lowercase();
+    best_bid::get_best_bid(&state.redis_pool, slot, &parent_hash, &proposer_pubkey)
+        .await?
+        .map(Json)
+        .ok_or_else(|| {
+            ApiError::not_found(format!(
+                "no bid stored for slot {slot}, parent hash {parent_hash} and proposer {proposer_pubkey}"
+            ))
+        })
+}
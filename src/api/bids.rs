@@ -0,0 +1,21 @@
+//! Lists what we stored for a slot, from the bid index.
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+
+use crate::{
+    bid_index::{self, Bid},
+    server::AppState,
+};
+
+use super::ApiError;
+
+/// The bids stored for the slot, highest value first.
+pub async fn get_slot_bids(
+    State(state): State<AppState>,
+    Path(slot): Path<u64>,
+) -> Result<Json<Vec<Bid>>, ApiError> {
+    let bids = bid_index::bids_for_slot(&state.redis_pool, slot).await?;
+    Ok(Json(bids))
+}
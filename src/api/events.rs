@@ -0,0 +1,167 @@
+//! A Server-Sent Events feed of the payloads we store, optionally filtered by slot or builder.
+use std::convert::Infallible;
+
+use axum::{
+    extract::{RawQuery, State},
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures::{stream, Stream};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tracing::error;
+
+use crate::{events::StoredEvent, metrics::STORED_EVENTS_MISSED, server::AppState};
+
+use super::ApiError;
+
+#[derive(Debug, Default)]
+pub struct StoredEventsFilter {
+    builder_pubkey: Option<String>,
+    slot: Option<u64>,
+}
+
+impl StoredEventsFilter {
+    fn from_query(query: &str) -> Result<Self, ApiError> {
+        let mut filter = Self::default();
+        for (name, value) in form_urlencoded::parse(query.as_bytes()) {
+            match name.as_ref() {
+                "builder_pubkey" => filter.builder_pubkey = Some(value.into_owned()),
+                "slot" => {
+                    let slot = value
+                        .parse()
+                        .map_err(|_| ApiError::bad_request(format!("invalid slot {value}")))?;
+                    filter.slot = Some(slot);
+                }
+                _ => return Err(ApiError::bad_request(format!("unknown filter {name}"))),
+            }
+        }
+        Ok(filter)
+    }
+
+    fn matches(&self, event: &StoredEvent) -> bool {
+        self.slot.is_none_or(|slot| slot == event.bid.slot)
+            && self.builder_pubkey.as_ref().is_none_or(|builder_pubkey| {
+                builder_pubkey.eq_ignore_ascii_case(&event.bid.builder_pubkey)
+            })
+    }
+}
+
+// Waits for the next stored event passing the filter.
+async fn recv_matching(
+    stored_events_rx: &mut Receiver<StoredEvent>,
+    filter: &StoredEventsFilter,
+) -> Result<StoredEvent, RecvError> {
+    loop {
+        let stored_event = stored_events_rx.recv().await?;
+        if filter.matches(&stored_event) {
+            return Ok(stored_event);
+        }
+    }
+}
+
+// A subscriber that fell too far behind gets a lagged event with the number of events it missed.
+async fn next_event(
+    (mut stored_events_rx, filter): (Receiver<StoredEvent>, StoredEventsFilter),
+) -> Option<(
+    Result<Event, Infallible>,
+    (Receiver<StoredEvent>, StoredEventsFilter),
+)> {
+    loop {
+        let event = match recv_matching(&mut stored_events_rx, &filter).await {
+            Ok(stored_event) => match Event::default().event("stored").json_data(&stored_event) {
+                Ok(event) => event,
+                Err(e) => {
+                    error!(?e, "failed to serialize stored event");
+                    continue;
+                }
+            },
+            Err(RecvError::Lagged(missed)) => {
+                STORED_EVENTS_MISSED.inc_by(missed);
+                Event::default().event("lagged").data(missed.to_string())
+            }
+            Err(RecvError::Closed) => return None,
+        };
+        return Some((Ok(event), (stored_events_rx, filter)));
+    }
+}
+
+/// Filters are passed as query parameters, e.g. `?slot=42&builder_pubkey=0xabc`.
+pub async fn get_stored_events(
+    State(state): State<AppState>,
+    RawQuery(query): RawQuery,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let filter = StoredEventsFilter::from_query(query.as_deref().unwrap_or_default())?;
+    let stored_events_rx = state.stored_events_tx.subscribe();
+    let stream = stream::unfold((stored_events_rx, filter), next_event);
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bid_index::Bid;
+
+    use super::*;
+
+    fn stored_event(slot: u64, builder_pubkey: &str) -> StoredEvent {
+        StoredEvent {
+            bid: Bid {
+                block_hash: "0xhash".to_string(),
+                builder_pubkey: builder_pubkey.to_string(),
+                key: "key".to_string(),
+                parent_hash: "0xparent".to_string(),
+                proposer_pubkey: "0xproposer".to_string(),
+                received_at: 1,
+                slot,
+                value: 2,
+            },
+            eligible_at: None,
+            stored_at: 3,
+        }
+    }
+
+    #[test]
+    fn filter_from_query() {
+        let filter = StoredEventsFilter::from_query("slot=42&builder_pubkey=0xabc").unwrap();
+        assert_eq!(filter.slot, Some(42));
+        assert_eq!(filter.builder_pubkey.as_deref(), Some("0xabc"));
+
+        assert!(StoredEventsFilter::from_query("").unwrap().slot.is_none());
+        assert!(StoredEventsFilter::from_query("slot=latest").is_err());
+        assert!(StoredEventsFilter::from_query("builder=0xabc").is_err());
+    }
+
+    #[test]
+    fn filter_matches_slot_and_builder() {
+        let event = stored_event(42, "0xabc");
+        assert!(StoredEventsFilter::default().matches(&event));
+
+        let filter = StoredEventsFilter {
+            builder_pubkey: Some("0xABC".to_string()),
+            slot: Some(42),
+        };
+        assert!(filter.matches(&event));
+        assert!(!filter.matches(&stored_event(43, "0xabc")));
+        assert!(!filter.matches(&stored_event(42, "0xdef")));
+    }
+
+    #[tokio::test]
+    async fn lagging_subscriber_is_told_what_it_missed() {
+        let (stored_events_tx, mut stored_events_rx) = tokio::sync::broadcast::channel(2);
+        for slot in 0..4 {
+            stored_events_tx.send(stored_event(slot, "0xabc")).unwrap();
+        }
+
+        let filter = StoredEventsFilter::default();
+        assert!(matches!(
+            recv_matching(&mut stored_events_rx, &filter).await,
+            Err(RecvError::Lagged(2))
+        ));
+        assert_eq!(
+            recv_matching(&mut stored_events_rx, &filter)
+                .await
+                .unwrap()
+                .bid
+                .slot,
+            2
+        );
+    }
+}
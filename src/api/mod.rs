@@ -0,0 +1,60 @@
+//! # API
+//!
+//! HTTP routes to inspect what we stored for the relay.
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use tracing::error;
+
+mod best_bid;
+mod bids;
+mod events;
+mod payloads;
+
+pub use best_bid::get_best_bid;
+pub use bids::get_slot_bids;
+pub use events::get_stored_events;
+pub use payloads::{get_payload_by_key, get_payload_by_parts};
+
+/// An error response, with a message explaining what went wrong.
+#[derive(Debug)]
+pub struct ApiError {
+    message: String,
+    status: StatusCode,
+}
+
+impl ApiError {
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            status: StatusCode::BAD_REQUEST,
+        }
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            status: StatusCode::NOT_FOUND,
+        }
+    }
+}
+
+// Anything we didn't anticipate, e.g. Redis being unreachable, is logged and returned as a 500.
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        error!(?e, "failed to handle api request");
+        Self {
+            message: format!("{e:#}"),
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(json!({ "message": self.message }))).into_response()
+    }
+}
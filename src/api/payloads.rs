@@ -0,0 +1,126 @@
+//! Reads back stored payloads, exactly as the relay would get them.
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderName, HeaderValue},
+    response::{IntoResponse, Response},
+};
+use fred::{prelude::KeysInterface, types::RedisValue};
+
+use crate::{
+    block_submission_key, env::ENV_CONFIG, metrics, server::AppState, slot_clock::SLOT_CLOCK,
+    storage, BlockSubmissionKey, Slot,
+};
+
+use super::ApiError;
+
+/// The time in milliseconds until the stored payload expires.
+const TTL_HEADER: HeaderName = HeaderName::from_static("x-ttl-ms");
+
+// Redis can't tell an expired key from one never written, but we know when payloads expire.
+fn not_found_reason(expires_at: Duration, now: Duration) -> String {
+    if expires_at <= now {
+        format!("expired {}s ago", (now - expires_at).as_secs())
+    } else {
+        "not found".to_string()
+    }
+}
+
+async fn get_payload(state: &AppState, key: BlockSubmissionKey) -> Result<Response, ApiError> {
+    let slot = u64::try_from(key.slot())
+        .map_err(|_| ApiError::bad_request(format!("invalid slot {}", key.slot())))?;
+    let key_str = key.to_string();
+
+    let payload: RedisValue = state
+        .redis_pool
+        .get(key_str.as_str())
+        .await
+        .map_err(metrics::redis_error("get"))
+        .with_context(|| format!("failed to get payload {key_str}"))?;
+    let payload = match payload.into_bytes() {
+        Some(payload) => payload,
+        None => {
+            let expires_at = storage::expires_at(&SLOT_CLOCK, slot, ENV_CONFIG.expiry_margin_slots);
+            let reason = not_found_reason(expires_at, SLOT_CLOCK.now());
+            return Err(ApiError::not_found(format!("payload {key_str} {reason}")));
+        }
+    };
+
+    // -1 when the key has no expiry, -2 when it expired since we read it. Either way the payload
+    // is still worth returning.
+    let ttl_ms: i64 = state
+        .redis_pool
+        .pttl(key_str.as_str())
+        .await
+        .map_err(metrics::redis_error("pttl"))
+        .with_context(|| format!("failed to get ttl of payload {key_str}"))?;
+
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            ),
+            (TTL_HEADER, HeaderValue::from(ttl_ms)),
+        ],
+        payload,
+    )
+        .into_response())
+}
+
+/// Looks up a payload by the parts of its key, the fork follows from the slot.
+pub async fn get_payload_by_parts(
+    State(state): State<AppState>,
+    Path((slot, proposer_pubkey, block_hash)): Path<(u64, String, String)>,
+) -> Result<Response, ApiError> {
+    let fork = ENV_CONFIG.network.fork_schedule.fork_at_slot(slot);
+    let slot =
+        Slot::try_from(slot).map_err(|_| ApiError::bad_request(format!("invalid slot {slot}")))?;
+    let key = BlockSubmissionKey::new(
+        fork,
+        slot,
+        proposer_pubkey.to_lowercase(),
+        block_hash.to_lowercase(),
+    );
+    get_payload(&state, key).await
+}
+
+/// Looks up a payload by its full key, as printed in our logs.
+pub async fn get_payload_by_key(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+) -> Result<Response, ApiError> {
+    // Otherwise a key from another network's logs would be looked up under ours.
+    if let Some(network) = block_submission_key::key_network(&key) {
+        if network != ENV_CONFIG.network.name {
+            return Err(ApiError::bad_request(format!(
+                "key {key} is for network {network}, this relay serves {}",
+                ENV_CONFIG.network
+            )));
+        }
+    }
+    let key = key
+        .parse::<BlockSubmissionKey>()
+        .map_err(|e| ApiError::bad_request(format!("invalid key {key}: {e}")))?;
+    get_payload(&state, key).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn not_found_reason_test() {
+        let expires_at = Duration::from_secs(1000);
+        assert_eq!(
+            not_found_reason(expires_at, Duration::from_secs(1030)),
+            "expired 30s ago"
+        );
+        assert_eq!(
+            not_found_reason(expires_at, Duration::from_secs(990)),
+            "not found"
+        );
+    }
+}
@@ -0,0 +1,387 @@
+//! # Archiver
+//!
+//! Payloads in Redis expire shortly after their slot. To keep a long-term history, storage also
+//! hands every submission it processed to the archiver. The archiver batches them per slot and
+//! uploads each batch as a gzipped NDJSON object, one full block submission per line, to an
+//! S3-compatible object store, or a local directory when USE_LOCAL_STORE is set.
+//!
+//! Objects are partitioned by network and slot:
+//! `{network}/{slot}/{writer}-{millis}-{seq}.ndjson.gz`. A slot usually ends up in several objects,
+//! from several replicas, the writer and sequence number keep their names unique.
+//!
+//! Archiving is best effort. Storage never waits for the archiver, when the archiver falls behind
+//! submissions are dropped, and uploads which keep failing are given up on. Both are counted.
+use std::{collections::BTreeMap, io::Write, path::Path, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use flate2::{write::GzEncoder, Compression};
+use futures::{select, FutureExt};
+use object_store::{
+    aws::AmazonS3Builder, local::LocalFileSystem, path::Path as ObjectPath, Attribute, Attributes,
+    BackoffConfig, ObjectStore, PutOptions, RetryConfig,
+};
+use tokio::{
+    sync::{
+        mpsc::{self, error::TrySendError},
+        Notify,
+    },
+    task::JoinHandle,
+    time::interval,
+};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    env::{self, ENV_CONFIG},
+    metrics::{
+        ARCHIVE_OBJECTS_UPLOADED, ARCHIVE_SUBMISSIONS_ARCHIVED, ARCHIVE_SUBMISSIONS_DROPPED,
+        ARCHIVE_UPLOAD_FAILURES,
+    },
+    slot_clock::SLOT_CLOCK,
+    BlockSubmission,
+};
+
+/// How many submissions may wait for the archiver before we start dropping them.
+pub const ARCHIVE_BUFFER_SIZE: usize = 4096;
+
+// Batches are uploaded at least this often, about once a slot.
+const ARCHIVE_FLUSH_INTERVAL: Duration = Duration::from_secs(12);
+
+// A slot's batch is uploaded early once it holds this many submissions.
+const ARCHIVE_MAX_BATCH_SIZE: usize = 512;
+
+// Retries back off from 500ms, doubling, giving up after four retries or a minute.
+const UPLOAD_RETRY_CONFIG: RetryConfig = RetryConfig {
+    backoff: BackoffConfig {
+        init_backoff: Duration::from_millis(500),
+        max_backoff: Duration::from_secs(8),
+        base: 2.0,
+    },
+    max_retries: 4,
+    retry_timeout: Duration::from_secs(60),
+};
+
+const CONTENT_TYPE: &str = "application/gzip";
+
+pub type ArchiveTx = mpsc::Sender<BlockSubmission>;
+pub type ArchiveRx = mpsc::Receiver<BlockSubmission>;
+
+pub fn archive_channel() -> (ArchiveTx, ArchiveRx) {
+    mpsc::channel(ARCHIVE_BUFFER_SIZE)
+}
+
+/// Hands a submission to the archiver without waiting, dropping it when the archiver is behind.
+pub fn archive(archive_tx: &ArchiveTx, block_submission: BlockSubmission) {
+    match archive_tx.try_send(block_submission) {
+        Ok(()) => {}
+        Err(TrySendError::Full(block_submission)) => {
+            warn!(
+                block_hash = block_submission.block_hash(),
+                "archive buffer full, dropping block submission"
+            );
+            ARCHIVE_SUBMISSIONS_DROPPED.inc();
+        }
+        // The archiver only stops after storage does.
+        Err(TrySendError::Closed(_)) => {
+            ARCHIVE_SUBMISSIONS_DROPPED.inc();
+        }
+    }
+}
+
+/// Where archive objects are written to.
+#[derive(Clone)]
+pub struct ArchiveStore {
+    // The local filesystem store doesn't support attributes, only S3 gets a content type.
+    attributes: Attributes,
+    object_store: Arc<dyn ObjectStore>,
+}
+
+impl ArchiveStore {
+    pub fn local(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create local store {}", dir.display()))?;
+        let object_store = LocalFileSystem::new_with_prefix(dir)
+            .with_context(|| format!("failed to open local store {}", dir.display()))?;
+        Ok(Self {
+            attributes: Attributes::new(),
+            object_store: Arc::new(object_store),
+        })
+    }
+
+    /// Builds the S3 client with our retry policy on top of the given configuration.
+    pub fn s3(builder: AmazonS3Builder) -> Result<Self> {
+        let object_store = builder
+            .with_retry(UPLOAD_RETRY_CONFIG)
+            .build()
+            .context("failed to build S3 client")?;
+        Ok(Self {
+            attributes: Attributes::from_iter([(Attribute::ContentType, CONTENT_TYPE)]),
+            object_store: Arc::new(object_store),
+        })
+    }
+
+    /// Credentials not set explicitly are picked up the way AWS SDKs do, from the AWS_* env vars,
+    /// a web identity token, or the container or instance metadata endpoints.
+    pub fn from_env() -> Result<Self> {
+        if ENV_CONFIG.use_local_store {
+            return Self::local(Path::new(&ENV_CONFIG.local_store_path));
+        }
+
+        let mut builder = AmazonS3Builder::from_env().with_bucket_name(&ENV_CONFIG.s3_bucket);
+        if let Some(endpoint) = ENV_CONFIG.s3_endpoint.as_deref() {
+            builder = builder
+                .with_endpoint(endpoint)
+                .with_allow_http(endpoint.starts_with("http://"));
+        }
+        if let Some(region) = ENV_CONFIG.s3_region.as_deref() {
+            builder = builder.with_region(region);
+        }
+        if let Some(access_key_id) = ENV_CONFIG.s3_access_key_id.as_deref() {
+            builder = builder.with_access_key_id(access_key_id);
+        }
+        if let Some(secret_access_key) = ENV_CONFIG.s3_secret_access_key.as_deref() {
+            builder = builder.with_secret_access_key(secret_access_key);
+        }
+        if let Some(session_token) = ENV_CONFIG.s3_session_token.as_deref() {
+            builder = builder.with_token(session_token);
+        }
+        Self::s3(builder)
+    }
+
+    pub fn object_store(&self) -> &Arc<dyn ObjectStore> {
+        &self.object_store
+    }
+
+    pub async fn put_object(&self, key: &str, body: Vec<u8>) -> Result<()> {
+        let options = PutOptions {
+            attributes: self.attributes.clone(),
+            ..PutOptions::default()
+        };
+        self.object_store
+            .put_opts(&ObjectPath::from(key), body.into(), options)
+            .await
+            .with_context(|| format!("failed to upload archive object {key}"))?;
+        Ok(())
+    }
+}
+
+pub fn object_key(network: &str, slot: u64, writer: &str, created_at_ms: u64, seq: u64) -> String {
+    format!("{network}/{slot}/{writer}-{created_at_ms}-{seq}.ndjson.gz")
+}
+
+/// Gzipped NDJSON, one block submission per line.
+pub fn encode_batch(block_submissions: &[BlockSubmission]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    let mut line = Vec::new();
+    for block_submission in block_submissions {
+        line.clear();
+        serde_json::to_writer(&mut line, block_submission)
+            .context("failed to serialize block submission")?;
+        // Payloads are kept as the raw JSON we received, which may be pretty printed. JSON strings
+        // can't contain raw line breaks, so any we find are whitespace and safe to replace.
+        for byte in line.iter_mut() {
+            if *byte == b'\n' || *byte == b'\r' {
+                *byte = b' ';
+            }
+        }
+        line.push(b'\n');
+        encoder
+            .write_all(&line)
+            .context("failed to compress archive batch")?;
+    }
+    encoder.finish().context("failed to compress archive batch")
+}
+
+#[derive(Default)]
+struct Batches {
+    by_slot: BTreeMap<u64, Vec<BlockSubmission>>,
+}
+
+impl Batches {
+    /// Adds a submission, returning its slot's batch once that is full.
+    fn push(&mut self, block_submission: BlockSubmission) -> Option<(u64, Vec<BlockSubmission>)> {
+        let slot = block_submission.message().slot;
+        let batch = self.by_slot.entry(slot).or_default();
+        batch.push(block_submission);
+        if batch.len() >= ARCHIVE_MAX_BATCH_SIZE {
+            self.by_slot.remove_entry(&slot)
+        } else {
+            None
+        }
+    }
+
+    fn take_all(&mut self) -> BTreeMap<u64, Vec<BlockSubmission>> {
+        std::mem::take(&mut self.by_slot)
+    }
+}
+
+struct Archiver {
+    archive_store: ArchiveStore,
+    network: String,
+    seq: u64,
+    writer: String,
+}
+
+impl Archiver {
+    fn new(archive_store: ArchiveStore) -> Self {
+        let writer = env::replica_name();
+        Self {
+            archive_store,
+            network: ENV_CONFIG.network.name.clone(),
+            seq: 0,
+            writer,
+        }
+    }
+
+    // Failing to archive should not stop us from storing submissions for the relay, errors are
+    // logged and counted.
+    async fn upload(&mut self, slot: u64, block_submissions: Vec<BlockSubmission>) {
+        self.seq += 1;
+        let now_millis = SLOT_CLOCK.now().as_millis() as u64;
+        let key = object_key(&self.network, slot, &self.writer, now_millis, self.seq);
+
+        let result = match encode_batch(&block_submissions) {
+            Ok(body) => self.archive_store.put_object(&key, body).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => {
+                debug!(
+                    key,
+                    count = block_submissions.len(),
+                    "uploaded archive object"
+                );
+                ARCHIVE_OBJECTS_UPLOADED.inc();
+                ARCHIVE_SUBMISSIONS_ARCHIVED.inc_by(block_submissions.len() as u64);
+            }
+            Err(e) => {
+                error!(
+                    key,
+                    count = block_submissions.len(),
+                    ?e,
+                    "failed to archive block submissions, dropping them"
+                );
+                ARCHIVE_UPLOAD_FAILURES.inc();
+                ARCHIVE_SUBMISSIONS_DROPPED.inc_by(block_submissions.len() as u64);
+            }
+        }
+    }
+
+    async fn upload_all(&mut self, batches: BTreeMap<u64, Vec<BlockSubmission>>) {
+        for (slot, block_submissions) in batches {
+            self.upload(slot, block_submissions).await;
+        }
+    }
+}
+
+/// Archives submissions until the channel closes or we're asked to shut down, then uploads
+/// whatever is left.
+pub fn run_archive_submissions_thread(
+    archive_rx: ArchiveRx,
+    archive_store: ArchiveStore,
+    shutdown_notify: Arc<Notify>,
+) -> JoinHandle<()> {
+    info!("starting archive submissions thread");
+    tokio::spawn({
+        let mut archive_rx = archive_rx;
+        async move {
+            let mut archiver = Archiver::new(archive_store);
+            let mut batches = Batches::default();
+            let mut flush_interval = interval(ARCHIVE_FLUSH_INTERVAL);
+
+            loop {
+                select! {
+                    _ = shutdown_notify.notified().fuse() => {
+                        info!("received shutdown signal, uploading final archive batches");
+                        break;
+                    },
+                    block_submission = archive_rx.recv().fuse() => match block_submission {
+                        Some(block_submission) => {
+                            if let Some((slot, batch)) = batches.push(block_submission) {
+                                archiver.upload(slot, batch).await;
+                            }
+                        }
+                        None => {
+                            info!("archive channel closed, uploading final archive batches");
+                            break;
+                        }
+                    },
+                    _ = flush_interval.tick().fuse() => {
+                        archiver.upload_all(batches.take_all()).await;
+                    },
+                }
+            }
+
+            while let Ok(block_submission) = archive_rx.try_recv() {
+                if let Some((slot, batch)) = batches.push(block_submission) {
+                    archiver.upload(slot, batch).await;
+                }
+            }
+            archiver.upload_all(batches.take_all()).await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    const FIXTURE_PATH: &str = "tests/fixtures/0xffe314e3f12d726cf9f4a4babfcbfc836ef53d3144469f886423a833c853e3ef.json.gz.decompressed";
+
+    lazy_static::lazy_static! {
+        static ref FIXTURE: String = std::fs::read_to_string(FIXTURE_PATH).unwrap();
+    }
+
+    fn read_fixture() -> BlockSubmission {
+        serde_json::from_str(&FIXTURE).unwrap()
+    }
+
+    #[test]
+    fn object_keys_are_partitioned_by_network_and_slot() {
+        assert_eq!(
+            object_key("mainnet", 42, "pod-0", 1_700_000_000_000, 3),
+            "mainnet/42/pod-0-1700000000000-3.ndjson.gz"
+        );
+    }
+
+    #[test]
+    fn encode_batch_round_trips() {
+        let block_submissions = vec![read_fixture(), read_fixture()];
+        let encoded = encode_batch(&block_submissions).unwrap();
+
+        let lines: Vec<String> = BufReader::new(GzDecoder::new(encoded.as_slice()))
+            .lines()
+            .collect::<std::io::Result<_>>()
+            .unwrap();
+        assert_eq!(lines.len(), 2);
+        for line in lines {
+            let decoded: BlockSubmission = serde_json::from_str(&line).unwrap();
+            assert_eq!(decoded.block_hash(), block_submissions[0].block_hash());
+            assert_eq!(decoded.received_at(), block_submissions[0].received_at());
+            assert_eq!(
+                serde_json::from_slice::<serde_json::Value>(decoded.payload.as_bytes()).unwrap(),
+                serde_json::from_slice::<serde_json::Value>(
+                    block_submissions[0].payload.as_bytes()
+                )
+                .unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn batches_fill_up_per_slot() {
+        let mut batches = Batches::default();
+        let slot = read_fixture().message().slot;
+        for _ in 1..ARCHIVE_MAX_BATCH_SIZE {
+            assert!(batches.push(read_fixture()).is_none());
+        }
+
+        let (full_slot, batch) = batches.push(read_fixture()).unwrap();
+        assert_eq!(full_slot, slot);
+        assert_eq!(batch.len(), ARCHIVE_MAX_BATCH_SIZE);
+        assert!(batches.take_all().is_empty());
+    }
+}
@@ -0,0 +1,212 @@
+//! # Best bid
+//!
+//! Tracks the best bid we stored per (slot, parent_hash, proposer_pubkey) the way the relay picks
+//! the bid it serves: the highest value among each builder's latest bid. A builder resubmitting a
+//! lower bid to cancel its earlier one can therefore lower the best bid. This gives us an
+//! independent check on the relay. Builders' latest bids and the best bid live in a single hash
+//! that is updated by a Lua script, so concurrent stores can't undo each other. Whenever the best
+//! bid changes we publish the new best bid, as JSON, on the best bid changes channel.
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use fred::{
+    clients::{Pipeline, RedisClient},
+    pool::RedisPool,
+    prelude::{ClientLike, HashesInterface, LuaInterface, RedisResult},
+    types::{ClusterHash, CustomCommand, RedisValue},
+    util::sha1_hash,
+};
+use lazy_static::lazy_static;
+
+use crate::{
+    bid_index::{Bid, BID_INDEX_PREFIX},
+    env::ENV_CONFIG,
+    metrics::{self, BEST_BID_CHANGES},
+};
+
+// Per builder the hash holds the value, received_at and JSON of its latest bid under
+// value:<builder>, received_at:<builder> and bid:<builder>. The best of those sits under builder
+// and bid. A bid received before the builder's latest one is ignored, it was already cancelled.
+// Only when the best builder lowers its bid do we have to look at every builder's latest bid.
+//
+// Values are decimal strings too large for Lua numbers. Without leading zeros, a longer string is
+// a larger value, and equally long strings compare like their values. Ties keep the current best.
+//
+// Returns the new best bid, or nil if it didn't change.
+const UPDATE_BEST_BID_SCRIPT: &str = r#"
+local function greater(a, b)
+    return #a > #b or (#a == #b and a > b)
+end
+
+local builder = ARGV[1]
+local value = ARGV[2]
+local latest = redis.call('HGET', KEYS[1], 'received_at:' .. builder)
+if latest and tonumber(latest) > tonumber(ARGV[3]) then
+    return false
+end
+
+local best_builder = redis.call('HGET', KEYS[1], 'builder')
+local best_value = best_builder and redis.call('HGET', KEYS[1], 'value:' .. best_builder)
+redis.call('HSET', KEYS[1], 'value:' .. builder, value, 'received_at:' .. builder, ARGV[3],
+    'bid:' .. builder, ARGV[4])
+redis.call('PEXPIREAT', KEYS[1], ARGV[5])
+
+if not best_builder or greater(value, best_value) or
+    (builder == best_builder and value == best_value) then
+    best_builder = builder
+elseif builder == best_builder then
+    best_value = value
+    local fields = redis.call('HGETALL', KEYS[1])
+    for i = 1, #fields, 2 do
+        if string.sub(fields[i], 1, 6) == 'value:' and greater(fields[i + 1], best_value) then
+            best_builder = string.sub(fields[i], 7)
+            best_value = fields[i + 1]
+        end
+    end
+else
+    return false
+end
+
+local best_bid = redis.call('HGET', KEYS[1], 'bid:' .. best_builder)
+if best_bid == redis.call('HGET', KEYS[1], 'bid') then
+    return false
+end
+redis.call('HSET', KEYS[1], 'builder', best_builder, 'bid', best_bid)
+redis.call('PUBLISH', ARGV[6], best_bid)
+return best_bid
+"#;
+
+lazy_static! {
+    // Stores call the script by its hash, the script itself is only sent to load it.
+    static ref UPDATE_BEST_BID_SHA: String = sha1_hash(UPDATE_BEST_BID_SCRIPT);
+}
+
+fn best_bid_key(network: &str, slot: u64, parent_hash: &str, proposer_pubkey: &str) -> String {
+    format!("{BID_INDEX_PREFIX}/{network}:best-bid:{slot}_{parent_hash}_{proposer_pubkey}")
+}
+
+/// The channel on which every new best bid is published.
+pub fn best_bid_changes_channel(network: &str) -> String {
+    format!("{BID_INDEX_PREFIX}/{network}:best-bid-changes")
+}
+
+/// Loads the update script into Redis' script cache, so stores can call it by its hash.
+pub async fn load_update_script(redis_pool: &RedisPool) -> Result<()> {
+    redis_pool
+        .script_load::<String, _>(UPDATE_BEST_BID_SCRIPT)
+        .await
+        .map_err(metrics::redis_error("script_load"))
+        .context("failed to load update best bid script")?;
+
+    Ok(())
+}
+
+fn update_args(bid: &Bid, expires_at: Duration) -> Result<Vec<String>> {
+    let bid_json = serde_json::to_string(bid).context("failed to serialize bid")?;
+    Ok(vec![
+        bid.builder_pubkey.clone(),
+        bid.value.to_string(),
+        bid.received_at.to_string(),
+        bid_json,
+        expires_at.as_millis().to_string(),
+        best_bid_changes_channel(&ENV_CONFIG.network.name),
+    ])
+}
+
+fn key_for(bid: &Bid) -> String {
+    best_bid_key(
+        &ENV_CONFIG.network.name,
+        bid.slot,
+        &bid.parent_hash,
+        &bid.proposer_pubkey,
+    )
+}
+
+/// Queues recording the bid as its builder's latest for its slot, parent hash and proposer on
+/// the pipeline, updating the best bid accordingly. The caller sends the pipeline and passes the
+/// reply to [`best_bid_from_reply`].
+pub async fn update_best_bid(
+    pipeline: &Pipeline<RedisClient>,
+    bid: &Bid,
+    expires_at: Duration,
+) -> Result<()> {
+    // fred can't pipeline EVALSHA, the key follows the hash and the number of keys.
+    let command = CustomCommand::new_static("EVALSHA", ClusterHash::Offset(2), false);
+    let mut args = vec![UPDATE_BEST_BID_SHA.clone(), "1".to_string(), key_for(bid)];
+    args.extend(update_args(bid, expires_at)?);
+    pipeline.custom::<(), _>(command, args).await?;
+
+    Ok(())
+}
+
+/// The new best bid, if the update changed it. When Redis lost the script, e.g. after a restart,
+/// we run the update again with the script itself, which also loads it.
+pub async fn best_bid_from_reply(
+    redis_pool: &RedisPool,
+    reply: RedisResult<RedisValue>,
+    bid: &Bid,
+    expires_at: Duration,
+) -> Result<Option<Bid>> {
+    let reply = match reply {
+        Err(e) if e.details().starts_with("NOSCRIPT") => redis_pool
+            .next()
+            .eval(
+                UPDATE_BEST_BID_SCRIPT,
+                key_for(bid),
+                update_args(bid, expires_at)?,
+            )
+            .await
+            .map_err(metrics::redis_error("eval")),
+        reply => reply.map_err(metrics::redis_error("evalsha")),
+    }
+    .with_context(|| format!("failed to update best bid with {}", bid.key))?;
+
+    // Lua's false is a null reply, or a boolean one on RESP3 connections.
+    match reply {
+        RedisValue::Null | RedisValue::Boolean(false) => Ok(None),
+        reply => {
+            BEST_BID_CHANGES.inc();
+            let best_bid_json = reply
+                .into_string()
+                .context("expect best bid to be a string")?;
+            serde_json::from_str(&best_bid_json)
+                .map(Some)
+                .context("failed to parse best bid")
+        }
+    }
+}
+
+pub async fn get_best_bid(
+    redis_pool: &RedisPool,
+    slot: u64,
+    parent_hash: &str,
+    proposer_pubkey: &str,
+) -> Result<Option<Bid>> {
+    let key = best_bid_key(&ENV_CONFIG.network.name, slot, parent_hash, proposer_pubkey);
+    let bid_json: Option<String> = redis_pool
+        .hget(key.as_str(), "bid")
+        .await
+        .map_err(metrics::redis_error("hget"))
+        .with_context(|| format!("failed to get best bid {key}"))?;
+
+    bid_json
+        .map(|bid_json| serde_json::from_str(&bid_json).context("failed to parse best bid"))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn best_bid_keys() {
+        assert_eq!(
+            best_bid_key("mainnet", 42, "0xparent", "0xproposer"),
+            "block-submission-service/mainnet:best-bid:42_0xparent_0xproposer"
+        );
+        assert_eq!(
+            best_bid_changes_channel("mainnet"),
+            "block-submission-service/mainnet:best-bid-changes"
+        );
+    }
+}
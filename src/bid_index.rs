@@ -0,0 +1,167 @@
+//! # Bid index
+//!
+//! Next to each stored payload we keep, per slot, a sorted set of payload keys scored by bid
+//! value, and a hash with the details of each bid. Both expire together with the slot's payloads,
+//! so everything we have for a slot is a single lookup instead of a SCAN over the keyspace.
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use fred::{
+    clients::{Pipeline, RedisClient},
+    pool::RedisPool,
+    prelude::{ClientLike, HashesInterface, RedisResult, SortedSetsInterface},
+    types::{ClusterHash, CustomCommand, RedisValue},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{env::ENV_CONFIG, metrics, payload::quantity, BlockSubmission};
+
+pub(crate) const BID_INDEX_PREFIX: &str = "block-submission-service";
+
+fn bids_key(network: &str, slot: u64) -> String {
+    format!("{BID_INDEX_PREFIX}/{network}:bids:{slot}")
+}
+
+fn bid_details_key(network: &str, slot: u64) -> String {
+    format!("{BID_INDEX_PREFIX}/{network}:bid-details:{slot}")
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Bid {
+    pub block_hash: String,
+    pub builder_pubkey: String,
+    /// The key the payload is stored under.
+    pub key: String,
+    pub parent_hash: String,
+    pub proposer_pubkey: String,
+    /// Milliseconds since the unix epoch.
+    pub received_at: u64,
+    pub slot: u64,
+    #[serde(with = "quantity")]
+    pub value: u128,
+}
+
+impl Bid {
+    pub fn new(block_submission: &BlockSubmission, key: String) -> Self {
+        Self {
+            block_hash: block_submission.block_hash(),
+            builder_pubkey: block_submission.builder_pubkey(),
+            key,
+            parent_hash: block_submission.parent_hash(),
+            proposer_pubkey: block_submission.proposer_pubkey(),
+            received_at: block_submission.received_at(),
+            slot: block_submission.message().slot,
+            value: block_submission.value(),
+        }
+    }
+}
+
+/// Expires the key at a unix timestamp in milliseconds, the precision payloads expire with. fred
+/// has no PEXPIREAT so we send it as a custom command.
+async fn pexpire_at(
+    pipeline: &Pipeline<RedisClient>,
+    key: &str,
+    expires_at: Duration,
+) -> RedisResult<()> {
+    let command = CustomCommand::new_static("PEXPIREAT", ClusterHash::FirstKey, false);
+    let args: Vec<RedisValue> = vec![key.into(), (expires_at.as_millis() as i64).into()];
+    pipeline.custom(command, args).await
+}
+
+/// Queues adding a stored payload's bid to its slot's index on the pipeline, expiring at the
+/// same time as the payload. The caller sends the pipeline, together with the payload itself.
+pub async fn index_bid(
+    pipeline: &Pipeline<RedisClient>,
+    bid: &Bid,
+    expires_at: Duration,
+) -> Result<()> {
+    let bids_key = bids_key(&ENV_CONFIG.network.name, bid.slot);
+    let bid_details_key = bid_details_key(&ENV_CONFIG.network.name, bid.slot);
+    let bid_json = serde_json::to_string(bid).context("failed to serialize bid")?;
+
+    // Sorted set scores are doubles, close enough to order by, the exact value is in the details.
+    pipeline
+        .zadd::<(), _, _>(
+            bids_key.as_str(),
+            None,
+            None,
+            false,
+            false,
+            (bid.value as f64, bid.key.as_str()),
+        )
+        .await?;
+    pipeline
+        .hset::<(), _, _>(bid_details_key.as_str(), (bid.key.as_str(), bid_json))
+        .await?;
+    pexpire_at(pipeline, bids_key.as_str(), expires_at).await?;
+    pexpire_at(pipeline, bid_details_key.as_str(), expires_at).await?;
+
+    Ok(())
+}
+
+/// All bids we stored for the slot, highest value first.
+pub async fn bids_for_slot(redis_pool: &RedisPool, slot: u64) -> Result<Vec<Bid>> {
+    let bids_key = bids_key(&ENV_CONFIG.network.name, slot);
+    let keys: Vec<String> = redis_pool
+        .zrevrange(bids_key.as_str(), 0, -1, false)
+        .await
+        .map_err(metrics::redis_error("zrevrange"))
+        .with_context(|| format!("failed to read bids {bids_key}"))?;
+
+    if keys.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let bid_details_key = bid_details_key(&ENV_CONFIG.network.name, slot);
+    let bid_jsons: Vec<Option<String>> = redis_pool
+        .hmget(bid_details_key.as_str(), keys)
+        .await
+        .map_err(metrics::redis_error("hmget"))
+        .with_context(|| format!("failed to read bid details {bid_details_key}"))?;
+
+    // Details may have expired in between our reads, we skip those bids.
+    bid_jsons
+        .into_iter()
+        .flatten()
+        .map(|bid_json| serde_json::from_str(&bid_json).context("failed to parse bid details"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn index_keys() {
+        assert_eq!(
+            bids_key("mainnet", 42),
+            "block-submission-service/mainnet:bids:42"
+        );
+        assert_eq!(
+            bid_details_key("mainnet", 42),
+            "block-submission-service/mainnet:bid-details:42"
+        );
+    }
+
+    #[test]
+    fn bid_value_is_a_decimal_string() {
+        let bid = Bid {
+            block_hash: "0xhash".to_string(),
+            builder_pubkey: "0xbuilder".to_string(),
+            key: "key".to_string(),
+            parent_hash: "0xparent".to_string(),
+            proposer_pubkey: "0xproposer".to_string(),
+            received_at: 1,
+            slot: 42,
+            value: u128::MAX,
+        };
+        let bid_json = serde_json::to_value(&bid).unwrap();
+        assert_eq!(
+            bid_json["value"],
+            json!("340282366920938463463374607431768211455")
+        );
+        assert_eq!(serde_json::from_value::<Bid>(bid_json).unwrap(), bid);
+    }
+}
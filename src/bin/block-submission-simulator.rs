@@ -159,7 +159,7 @@ async fn main() -> Result<()> {
         let block_hash = block_submission.block_hash();
 
         client
-            .xadd(STREAM_NAME, false, None, "*", block_submission)
+            .xadd::<(), _, _, _, _>(STREAM_NAME, false, None, "*", block_submission)
             .await?;
 
         debug!(%slot, %proposer_pubkey, %block_hash, "simulated block submission");
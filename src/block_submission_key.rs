@@ -1,14 +1,26 @@
 use anyhow::anyhow;
 use std::{fmt::Display, str::FromStr};
 
-use crate::{env::ENV_CONFIG, Slot};
+use crate::{env::ENV_CONFIG, fork::Fork, Slot};
 
 const MEVBOOST_REDIS_PREFIX: &str = "boost-relay";
 const CAPELLA_PREFIX: &str = "cache-execpayload-capella-json";
+// From Deneb onwards payloads are stored together with their blobs bundle.
+const DENEB_PREFIX: &str = "cache-payload-contents-deneb-json";
+const ELECTRA_PREFIX: &str = "cache-payload-contents-electra-json";
+
+fn fork_prefix(fork: Fork) -> &'static str {
+    match fork {
+        Fork::Capella => CAPELLA_PREFIX,
+        Fork::Deneb => DENEB_PREFIX,
+        Fork::Electra => ELECTRA_PREFIX,
+    }
+}
 
 #[derive(Debug, Eq, Hash, PartialEq)]
 pub struct BlockSubmissionKey {
     block_hash: BlockHash,
+    fork: Fork,
     proposer_pubkey: ProposerPubkey,
     slot: Slot,
 }
@@ -17,19 +29,56 @@ type ProposerPubkey = String;
 type BlockHash = String;
 
 impl BlockSubmissionKey {
-    pub fn new(slot: Slot, proposer_pubkey: ProposerPubkey, block_hash: BlockHash) -> Self {
+    pub fn new(
+        fork: Fork,
+        slot: Slot,
+        proposer_pubkey: ProposerPubkey,
+        block_hash: BlockHash,
+    ) -> Self {
         Self {
             block_hash,
+            fork,
             proposer_pubkey,
             slot,
         }
     }
+
+    pub fn fork(&self) -> Fork {
+        self.fork
+    }
+
+    pub fn slot(&self) -> Slot {
+        self.slot
+    }
+}
+
+/// The network a full key was written for, `None` for a short key, which has no network.
+pub fn key_network(s: &str) -> Option<&str> {
+    let (prefix, _) = s.split_once(':')?;
+    prefix
+        .strip_prefix(MEVBOOST_REDIS_PREFIX)
+        .and_then(|network| network.strip_prefix('/'))
 }
 
 impl FromStr for BlockSubmissionKey {
     type Err = anyhow::Error;
 
+    /// Parses either a full key, or just its `<slot>_<proposer_pubkey>_<block_hash>` part, which
+    /// is taken to be a Capella key.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (fork, s) = match s.rsplit_once(':') {
+            None => (Fork::Capella, s),
+            Some((prefix, s)) => {
+                let fork = match prefix.rsplit(':').next() {
+                    Some(CAPELLA_PREFIX) => Fork::Capella,
+                    Some(DENEB_PREFIX) => Fork::Deneb,
+                    Some(ELECTRA_PREFIX) => Fork::Electra,
+                    _ => return Err(anyhow!("unknown key prefix {prefix}")),
+                };
+                (fork, s)
+            }
+        };
+
         let mut parts = s.split('_');
         let slot = parts.next().ok_or_else(|| anyhow!("missing slot"))?;
         let proposer_pubkey = parts
@@ -39,6 +88,7 @@ impl FromStr for BlockSubmissionKey {
 
         Ok(Self {
             block_hash: block_hash.to_string(),
+            fork,
             proposer_pubkey: proposer_pubkey.to_string(),
             slot: slot.parse::<i32>()?,
         })
@@ -49,8 +99,56 @@ impl Display for BlockSubmissionKey {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "{MEVBOOST_REDIS_PREFIX}/{}:{CAPELLA_PREFIX}:{}_{}_{}",
-            ENV_CONFIG.network, self.slot, self.proposer_pubkey, self.block_hash
+            "{MEVBOOST_REDIS_PREFIX}/{}:{}:{}_{}_{}",
+            ENV_CONFIG.network,
+            fork_prefix(self.fork),
+            self.slot,
+            self.proposer_pubkey,
+            self.block_hash
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_short_key() {
+        let key: BlockSubmissionKey = "42_0xproposer_0xhash".parse().unwrap();
+        assert_eq!(
+            key,
+            BlockSubmissionKey::new(
+                Fork::Capella,
+                42,
+                "0xproposer".to_string(),
+                "0xhash".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn parse_full_key() {
+        let key: BlockSubmissionKey =
+            "boost-relay/mainnet:cache-payload-contents-deneb-json:42_0xproposer_0xhash"
+                .parse()
+                .unwrap();
+        assert_eq!(key.fork(), Fork::Deneb);
+        assert_eq!(key.slot, 42);
+
+        assert!(
+            "boost-relay/mainnet:cache-something-else:42_0xproposer_0xhash"
+                .parse::<BlockSubmissionKey>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn key_network_test() {
+        assert_eq!(
+            key_network("boost-relay/holesky:cache-payload-contents-deneb-json:42_0xp_0xh"),
+            Some("holesky")
+        );
+        assert_eq!(key_network("42_0xproposer_0xhash"), None);
+    }
+}
@@ -1,13 +1,20 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, ops::Range};
 
 use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
 use fred::{
     prelude::{RedisError, RedisErrorKind},
     types::{FromRedis, MultipleOrderedPairs, RedisKey, RedisMap, RedisValue},
 };
-use serde::{Deserialize, Serialize};
-
-use crate::{BlockSubmissionKey, Slot};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::value::RawValue;
+
+use crate::{
+    env::ENV_CONFIG,
+    fork::{Fork, ForkSchedule},
+    payload::{BidTrace, SignedBidSubmission},
+    BlockSubmissionKey, Slot,
+};
 
 fn deserialize_eligible_at<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
 where
@@ -28,6 +35,163 @@ where
     Ok(eligible_at)
 }
 
+/// The block submission payload as the raw JSON bytes we received. Payloads are megabytes in size
+/// and we don't do anything with most of it but store it again, so instead of parsing it into a
+/// `serde_json::Value` we decode only the small `BidTrace` message and remember where the
+/// execution payload (and blobs bundle) sit in the bytes. This also keeps the stored execution
+/// payload byte-exact.
+#[derive(Clone)]
+pub struct RawPayload {
+    bytes: Bytes,
+    blobs_bundle: Option<Range<usize>>,
+    execution_payload: Option<Range<usize>>,
+    has_execution_requests: bool,
+    message: BidTrace,
+}
+
+impl RawPayload {
+    pub fn from_bytes(bytes: Bytes) -> serde_json::Result<Self> {
+        #[derive(Deserialize)]
+        struct Fields<'a> {
+            #[serde(borrow)]
+            blobs_bundle: Option<&'a RawValue>,
+            #[serde(borrow)]
+            execution_payload: Option<&'a RawValue>,
+            #[serde(borrow)]
+            execution_requests: Option<&'a RawValue>,
+            message: BidTrace,
+        }
+
+        // The raw values borrow from our bytes, their offset gives us the range to slice.
+        let range_of = |raw: &RawValue| {
+            let start = raw.get().as_ptr() as usize - bytes.as_ptr() as usize;
+            start..start + raw.get().len()
+        };
+
+        // A payload without a message, e.g. null, fails here and ends up in the dead-letter stream.
+        let fields: Fields = serde_json::from_slice(&bytes)?;
+        let blobs_bundle = fields.blobs_bundle.map(range_of);
+        let execution_payload = fields.execution_payload.map(range_of);
+        let has_execution_requests = fields.execution_requests.is_some();
+        let message = fields.message;
+
+        Ok(Self {
+            blobs_bundle,
+            bytes,
+            execution_payload,
+            has_execution_requests,
+            message,
+        })
+    }
+
+    /// Checks the payload has the fields the given fork expects.
+    pub fn check_fork(&self, fork: Fork) -> Result<(), String> {
+        // Execution payloads carry the blob gas fields from Deneb onwards.
+        #[derive(Deserialize)]
+        #[allow(dead_code)]
+        struct BlobGas {
+            blob_gas_used: String,
+            excess_blob_gas: String,
+        }
+
+        match fork {
+            Fork::Capella => {
+                if self.blobs_bundle.is_some() {
+                    return Err("capella payload has a blobs bundle".to_string());
+                }
+            }
+            Fork::Deneb | Fork::Electra => {
+                if self.blobs_bundle.is_none() {
+                    return Err(format!("{fork} payload is missing its blobs bundle"));
+                }
+                serde_json::from_slice::<BlobGas>(&self.execution_payload()).map_err(|e| {
+                    format!("{fork} execution payload is missing blob gas fields, {e}")
+                })?;
+                if fork == Fork::Electra && !self.has_execution_requests {
+                    return Err("electra payload is missing its execution requests".to_string());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn as_bytes(&self) -> &Bytes {
+        &self.bytes
+    }
+
+    pub fn message(&self) -> &BidTrace {
+        &self.message
+    }
+
+    /// Decodes the full payload, including the execution payload. Expensive, the service itself
+    /// never needs this.
+    pub fn decode(&self) -> serde_json::Result<SignedBidSubmission> {
+        serde_json::from_slice(&self.bytes)
+    }
+
+    fn as_str(&self) -> &str {
+        // We only construct a RawPayload from bytes that parsed as JSON, which means valid UTF-8.
+        std::str::from_utf8(&self.bytes).expect("expect payload to be valid UTF-8")
+    }
+
+    /// The execution payload JSON, a cheap slice of the payload bytes.
+    pub fn execution_payload(&self) -> Bytes {
+        match self.execution_payload.as_ref() {
+            Some(range) => self.bytes.slice(range.clone()),
+            None => Bytes::from_static(b"null"),
+        }
+    }
+
+    /// What the relay expects to find under the submission's key. The execution payload for
+    /// Capella, the execution payload and blobs bundle from Deneb onwards.
+    pub fn stored_payload(&self) -> Bytes {
+        match self.blobs_bundle.as_ref() {
+            None => self.execution_payload(),
+            Some(blobs_bundle) => {
+                let execution_payload = self.execution_payload();
+                let blobs_bundle = &self.bytes[blobs_bundle.clone()];
+                let mut bytes =
+                    BytesMut::with_capacity(execution_payload.len() + blobs_bundle.len() + 40);
+                bytes.put_slice(b"{\"execution_payload\":");
+                bytes.put_slice(&execution_payload);
+                bytes.put_slice(b",\"blobs_bundle\":");
+                bytes.put_slice(blobs_bundle);
+                bytes.put_u8(b'}');
+                bytes.freeze()
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for RawPayload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<PAYLOAD_JSON:{} bytes>", self.bytes.len())
+    }
+}
+
+impl PartialEq for RawPayload {
+    fn eq(&self, other: &Self) -> bool {
+        self.bytes == other.bytes
+    }
+}
+
+impl Serialize for RawPayload {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let raw: &RawValue =
+            serde_json::from_str(self.as_str()).map_err(serde::ser::Error::custom)?;
+        raw.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for RawPayload {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = Box::<RawValue>::deserialize(deserializer)?;
+        let bytes = Bytes::from(String::from(Box::<str>::from(raw)));
+        RawPayload::from_bytes(bytes).map_err(serde::de::Error::custom)
+    }
+}
+
 /// Block submission archive entries.
 /// These are block submissions as they came in on the relay, plus some metadata.
 #[derive(Deserialize, Serialize)]
@@ -35,7 +199,7 @@ pub struct BlockSubmission {
     // Not every block submission becomes eligible, so this field is optional.
     #[serde(deserialize_with = "deserialize_eligible_at")]
     eligible_at: Option<u64>,
-    pub payload: serde_json::Value,
+    pub payload: RawPayload,
     received_at: u64,
     // Optional until builder-api is updated to send it.
     safe_to_propose: Option<bool>,
@@ -57,7 +221,7 @@ impl std::fmt::Debug for BlockSubmission {
         f.debug_struct("BlockSubmission")
             .field("eligible_at", &self.eligible_at)
             .field("sim_optimistic", &self.sim_optimistic)
-            .field("payload", &format!("<PAYLOAD_JSON:{state_root}>"))
+            .field("payload", &format!("<PAYLOAD_JSON:{state_root:?}>"))
             .field("received_at", &self.received_at)
             .field("safe_to_propose", &self.safe_to_propose)
             .field("sim_request_error", &self.sim_request_error)
@@ -73,7 +237,7 @@ impl From<BlockSubmission> for MultipleOrderedPairs {
         let mut pairs: Vec<(RedisKey, RedisValue)> = vec![
             (
                 "payload".into(),
-                RedisValue::String(serde_json::to_string(&entry.payload).unwrap().into()),
+                RedisValue::Bytes(entry.payload.as_bytes().clone()),
             ),
             (
                 "received_at".into(),
@@ -153,18 +317,13 @@ impl FromRedis for BlockSubmission {
         };
 
         let payload = {
-            let str = map
+            let bytes = map
                 .remove("payload")
                 .ok_or_else(|| into_redis_parse_err("expected payload in block submission"))?
-                .as_bytes_str()
-                .ok_or_else(|| into_redis_parse_err("failed to parse payload as bytes str"))?;
-            // We could implement custom Deserialize for this to avoid parsing the JSON here, we
-            // don't do anything with it besides Serialize it later.
-            serde_json::from_str(&str).map_err(|e| {
-                into_redis_parse_err(format!(
-                    "failed to parse payload bytes as serde_json Value, {}",
-                    e
-                ))
+                .into_bytes()
+                .ok_or_else(|| into_redis_parse_err("failed to parse payload as bytes"))?;
+            RawPayload::from_bytes(bytes).map_err(|e| {
+                into_redis_parse_err(format!("failed to parse payload bytes as JSON, {}", e))
             })?
         };
 
@@ -272,7 +431,7 @@ impl From<BlockSubmission> for RedisValue {
         }
         map.insert(
             "payload".into(),
-            RedisValue::String(entry.payload.to_string().into()),
+            RedisValue::Bytes(entry.payload.as_bytes().clone()),
         );
         map.insert(
             "received_at".into(),
@@ -303,12 +462,24 @@ impl From<BlockSubmission> for RedisValue {
     }
 }
 
+// Every payload needs a message, the default has one with zeroed fields.
+const DEFAULT_PAYLOAD: &str = concat!(
+    r#"{"message":{"slot":"0","#,
+    r#""parent_hash":"0x0000000000000000000000000000000000000000000000000000000000000000","#,
+    r#""block_hash":"0x0000000000000000000000000000000000000000000000000000000000000000","#,
+    r#""builder_pubkey":"0x000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000","#,
+    r#""proposer_pubkey":"0x000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000","#,
+    r#""proposer_fee_recipient":"0x0000000000000000000000000000000000000000","#,
+    r#""gas_limit":"0","gas_used":"0","value":"0"}}"#
+);
+
 impl Default for BlockSubmission {
     fn default() -> Self {
         Self {
             eligible_at: None,
             sim_optimistic: None,
-            payload: serde_json::Value::Null,
+            payload: RawPayload::from_bytes(Bytes::from_static(DEFAULT_PAYLOAD.as_bytes()))
+                .expect("expect default payload to be valid"),
             received_at: 0,
             safe_to_propose: None,
             sim_request_error: None,
@@ -320,41 +491,103 @@ impl Default for BlockSubmission {
 }
 
 impl BlockSubmission {
+    pub fn message(&self) -> &BidTrace {
+        self.payload.message()
+    }
+
     pub fn block_hash(&self) -> String {
-        self.payload["message"]["block_hash"]
-            .as_str()
-            .unwrap()
-            .to_string()
+        self.message().block_hash.to_string()
+    }
+
+    pub fn builder_pubkey(&self) -> String {
+        self.message().builder_pubkey.to_string()
+    }
+
+    pub fn parent_hash(&self) -> String {
+        self.message().parent_hash.to_string()
     }
 
     pub fn block_submission_key(&self) -> BlockSubmissionKey {
         let slot = self.slot();
         let proposer_pubkey = self.proposer_pubkey();
         let block_hash = self.block_hash();
-        BlockSubmissionKey::new(slot, proposer_pubkey, block_hash)
+        BlockSubmissionKey::new(self.fork(), slot, proposer_pubkey, block_hash)
+    }
+
+    pub fn eligible_at(&self) -> Option<u64> {
+        self.eligible_at
+    }
+
+    pub fn execution_payload(&self) -> Bytes {
+        self.payload.execution_payload()
+    }
+
+    /// The fork the submission's slot falls in, according to the network's fork schedule.
+    pub fn fork(&self) -> Fork {
+        ENV_CONFIG
+            .network
+            .fork_schedule
+            .fork_at_slot(self.message().slot)
+    }
+
+    /// Checks the payload has the fields its fork, according to the fork schedule, expects.
+    pub fn check_fork(&self, fork_schedule: &ForkSchedule) -> Result<(), String> {
+        self.payload
+            .check_fork(fork_schedule.fork_at_slot(self.message().slot))
     }
 
-    pub fn execution_payload(&self) -> serde_json::Value {
-        self.payload["execution_payload"].clone()
+    pub fn stored_payload(&self) -> Bytes {
+        self.payload.stored_payload()
+    }
+
+    pub fn received_at(&self) -> u64 {
+        self.received_at
     }
 
     pub fn proposer_pubkey(&self) -> String {
-        self.payload["message"]["proposer_pubkey"]
-            .as_str()
-            .unwrap()
-            .to_string()
+        self.message().proposer_pubkey.to_string()
+    }
+
+    pub fn sim_optimistic(&self) -> Option<bool> {
+        self.sim_optimistic
+    }
+
+    pub fn sim_request_error(&self) -> Option<&str> {
+        self.sim_request_error.as_deref()
+    }
+
+    pub fn sim_validation_error(&self) -> Option<&str> {
+        self.sim_validation_error.as_deref()
+    }
+
+    pub fn sim_was_simulated(&self) -> Option<bool> {
+        self.sim_was_simulated
     }
 
     pub fn slot(&self) -> Slot {
-        let slot_str = self.payload["message"]["slot"].as_str().unwrap();
-        slot_str.parse::<i32>().unwrap()
+        Slot::try_from(self.message().slot).expect("expect slot to fit in a Slot")
+    }
+
+    // Only used to identify submissions in debug output, so we don't mind parsing for it.
+    fn state_root(&self) -> Option<String> {
+        #[derive(Deserialize)]
+        struct StateRoot {
+            state_root: Option<String>,
+        }
+
+        serde_json::from_slice::<Option<StateRoot>>(&self.execution_payload())
+            .ok()
+            .flatten()
+            .and_then(|execution_payload| execution_payload.state_root)
+    }
+
+    pub fn status_code(&self) -> Option<u16> {
+        self.status_code
     }
 
-    fn state_root(&self) -> String {
-        self.payload["execution_payload"]["state_root"]
-            .as_str()
-            .unwrap()
-            .to_string()
+    /// The bid value in wei.
+    pub fn value(&self) -> u128 {
+        self.message().value
     }
 
     // Not every archived block submission is accepted by the relay. It signals to us which
@@ -370,11 +603,19 @@ mod tests {
     use fred::types::{RedisMap, RedisValue};
     use serde_json::json;
 
+    const MESSAGE: &str = r#"{"slot":"42","parent_hash":"0xa82c987560729d49754f66858d7f25f460908d5477087cd701aaf5920ad305cd","block_hash":"0xe2b6337a01144887af67f9b2cfb77df39d62033fedcfd3cb70d45035ff79ae3b","builder_pubkey":"0x9192ca93e6c9b499d730a153e3dfba05bc13a91d50750265793de1f2488a66a7f3232f7b1ef374150365b9ea2cf9824d","proposer_pubkey":"0x8567a285bb5eee7373fb0f71df1f5a3367f6fa3fdf4a4931f51cd8bf56c00d6ce77836051ea1f797a21f9bd7b12275fd","proposer_fee_recipient":"0x388C818CA8B9251b393131C08a736A67ccB19297","gas_limit":"29999972","gas_used":"6635538","value":"13620198421388901"}"#;
+
+    fn payload_str(execution_payload: &str) -> String {
+        format!(r#"{{"message":{MESSAGE},"execution_payload":{execution_payload}}}"#)
+    }
+
     #[test]
     fn create_block_submission() {
         let mut submission = BlockSubmission::default();
-        let payload =
-            json!({"message": {"slot": "42"}, "execution_payload": {"state_root": "some_root"}});
+        let payload = RawPayload::from_bytes(Bytes::from(payload_str(
+            &json!({"state_root": "some_root"}).to_string(),
+        )))
+        .unwrap();
         submission.eligible_at = Some(100);
         submission.payload = payload.clone();
         submission.received_at = 200;
@@ -392,10 +633,7 @@ mod tests {
         redis_map.insert("eligible_at".into(), RedisValue::String("100".into()));
         redis_map.insert(
             "payload".into(),
-            RedisValue::String(
-                "{\"message\": {\"slot\": \"42\"}, \"execution_payload\": {\"state_root\": \"some_root\"}}"
-                    .into(),
-            ),
+            RedisValue::String(payload_str(r#"{"state_root": "some_root"}"#).into()),
         );
         redis_map.insert("received_at".into(), RedisValue::String("200".into()));
         redis_map.insert("status_code".into(), RedisValue::String("400".into()));
@@ -407,5 +645,108 @@ mod tests {
         assert_eq!(submission.eligible_at, Some(100));
         assert_eq!(submission.received_at, 200);
         assert_eq!(submission.status_code, Some(400));
+        assert_eq!(submission.slot(), 42);
+        assert_eq!(
+            submission.block_hash(),
+            "0xe2b6337a01144887af67f9b2cfb77df39d62033fedcfd3cb70d45035ff79ae3b"
+        );
+        assert_eq!(submission.value(), 13620198421388901);
+    }
+
+    #[test]
+    fn invalid_message_fails_to_decode() {
+        let payload = r#"{"message":{"slot":"42"},"execution_payload":{}}"#;
+        assert!(RawPayload::from_bytes(Bytes::from(payload)).is_err());
+    }
+
+    #[test]
+    fn payload_without_message_fails_to_decode() {
+        assert!(RawPayload::from_bytes(Bytes::from_static(b"null")).is_err());
+        assert!(RawPayload::from_bytes(Bytes::from(r#"{"execution_payload":{}}"#)).is_err());
+
+        let mut redis_map = RedisMap::new();
+        redis_map.insert("payload".into(), RedisValue::String("null".into()));
+        redis_map.insert("received_at".into(), RedisValue::String("200".into()));
+        assert!(BlockSubmission::from_value(RedisValue::Map(redis_map)).is_err());
+    }
+
+    #[test]
+    fn default_block_submission_has_a_message() {
+        let submission = BlockSubmission::default();
+        assert_eq!(submission.message().slot, 0);
+        assert_eq!(submission.value(), 0);
+    }
+
+    #[test]
+    fn execution_payload_is_byte_exact() {
+        let raw = payload_str(r#"{"z": "1",  "a": [1, 2]}"#);
+        let payload = RawPayload::from_bytes(Bytes::from(raw)).unwrap();
+
+        assert_eq!(
+            payload.execution_payload(),
+            Bytes::from(r#"{"z": "1",  "a": [1, 2]}"#)
+        );
+    }
+
+    #[test]
+    fn capella_stores_execution_payload() {
+        let payload =
+            RawPayload::from_bytes(Bytes::from(payload_str(r#"{"state_root":"0x"}"#))).unwrap();
+
+        assert_eq!(payload.check_fork(Fork::Capella), Ok(()));
+        assert!(payload.check_fork(Fork::Deneb).is_err());
+        assert_eq!(
+            payload.stored_payload(),
+            Bytes::from(r#"{"state_root":"0x"}"#)
+        );
+    }
+
+    #[test]
+    fn deneb_stores_execution_payload_and_blobs_bundle() {
+        let raw = format!(
+            r#"{{"message":{MESSAGE},"execution_payload":{{"blob_gas_used":"0","excess_blob_gas":"0"}},"blobs_bundle":{{"commitments":[],"proofs":[],"blobs":[]}}}}"#
+        );
+        let payload = RawPayload::from_bytes(Bytes::from(raw)).unwrap();
+
+        assert_eq!(payload.check_fork(Fork::Deneb), Ok(()));
+        assert!(payload.check_fork(Fork::Capella).is_err());
+        assert!(payload.check_fork(Fork::Electra).is_err());
+        assert_eq!(
+            payload.stored_payload(),
+            Bytes::from(
+                r#"{"execution_payload":{"blob_gas_used":"0","excess_blob_gas":"0"},"blobs_bundle":{"commitments":[],"proofs":[],"blobs":[]}}"#
+            )
+        );
+    }
+
+    #[test]
+    fn deneb_requires_blob_gas_fields() {
+        let raw = format!(
+            r#"{{"message":{MESSAGE},"execution_payload":{{}},"blobs_bundle":{{"commitments":[],"proofs":[],"blobs":[]}}}}"#
+        );
+        let payload = RawPayload::from_bytes(Bytes::from(raw)).unwrap();
+        assert!(payload.check_fork(Fork::Deneb).is_err());
+    }
+
+    #[test]
+    fn electra_requires_execution_requests() {
+        let raw = format!(
+            r#"{{"message":{MESSAGE},"execution_payload":{{"blob_gas_used":"0","excess_blob_gas":"0"}},"blobs_bundle":{{"commitments":[],"proofs":[],"blobs":[]}},"execution_requests":{{"deposits":[],"withdrawals":[],"consolidations":[]}}}}"#
+        );
+        let payload = RawPayload::from_bytes(Bytes::from(raw)).unwrap();
+        assert_eq!(payload.check_fork(Fork::Electra), Ok(()));
+    }
+
+    #[test]
+    fn serde_round_trip_keeps_payload_bytes() {
+        let payload = payload_str(r#"{"b":1,"a":2}"#);
+        let raw = format!(r#"{{"eligible_at":null,"payload":{payload},"received_at":200}}"#);
+        let submission: BlockSubmission = serde_json::from_str(&raw).unwrap();
+
+        assert_eq!(submission.payload.as_bytes(), &Bytes::from(payload.clone()));
+        assert_eq!(submission.slot(), 42);
+
+        let serialized = serde_json::to_string(&submission).unwrap();
+        assert!(serialized.contains(&format!(r#""payload":{payload}"#)));
     }
 }
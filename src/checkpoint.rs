@@ -0,0 +1,308 @@
+//! # Checkpoint
+//!
+//! Persists, per stream, the ID of the last stream entry we stored, so a restart can resume where
+//! we left off instead of jumping to the newest entry and skipping everything published during a
+//! deploy. The checkpoints are kept in a single Redis hash, keyed by stream name.
+//!
+//! Submissions are stored concurrently and may finish out of order. We therefore track every ID
+//! read, in order, and only move the checkpoint past an ID once it and every ID before it has been
+//! stored or skipped.
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use fred::{pool::RedisPool, prelude::HashesInterface};
+use futures::{select, FutureExt};
+use tokio::{sync::Notify, task::JoinHandle, time::interval};
+use tracing::{debug, error, info, trace, warn};
+
+use crate::{env::ENV_CONFIG, metrics};
+
+// How often we write the checkpoint to Redis.
+const CHECKPOINT_WRITE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Stream IDs look like <milliseconds since epoch>-<sequence number>.
+pub fn stream_id_millis(id: &str) -> Option<u64> {
+    id.split('-').next().and_then(|millis| millis.parse().ok())
+}
+
+/// The milliseconds and sequence number of a stream ID, which order stream IDs.
+pub fn parse_stream_id(id: &str) -> Option<(u64, u64)> {
+    let (millis, sequence) = id.split_once('-')?;
+    Some((millis.parse().ok()?, sequence.parse().ok()?))
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("expect system time to be after the unix epoch")
+        .as_millis() as u64
+}
+
+#[derive(Debug, Default)]
+struct CheckpointState {
+    // IDs read but not yet done, in stream order, with whether they've been stored (or skipped).
+    in_flight: VecDeque<(String, bool)>,
+    last_stored_id: Option<String>,
+}
+
+impl CheckpointState {
+    fn advance(&mut self) {
+        while let Some((_, true)) = self.in_flight.front() {
+            let (id, _) = self.in_flight.pop_front().unwrap();
+            self.last_stored_id = Some(id);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Checkpoint {
+    streams: Arc<Mutex<HashMap<String, CheckpointState>>>,
+}
+
+impl Checkpoint {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, CheckpointState>> {
+        self.streams
+            .lock()
+            .expect("expect to be able to acquire checkpoint lock")
+    }
+
+    /// Register an ID as read. IDs have to be tracked in the order they were read.
+    pub fn track(&self, stream: &str, id: &str) {
+        self.lock()
+            .entry(stream.to_string())
+            .or_default()
+            .in_flight
+            .push_back((id.to_string(), false));
+    }
+
+    /// Mark an ID as done, either because it was stored or because it was skipped.
+    pub fn mark_done(&self, stream: &str, id: &str) {
+        let mut streams = self.lock();
+        if let Some(state) = streams.get_mut(stream) {
+            if let Some(entry) = state
+                .in_flight
+                .iter_mut()
+                .find(|(in_flight_id, _)| in_flight_id == id)
+            {
+                entry.1 = true;
+            }
+            state.advance();
+        }
+    }
+
+    pub fn last_stored_id(&self, stream: &str) -> Option<String> {
+        self.lock()
+            .get(stream)
+            .and_then(|state| state.last_stored_id.clone())
+    }
+
+    fn last_stored_ids(&self) -> HashMap<String, String> {
+        self.lock()
+            .iter()
+            .filter_map(|(stream, state)| {
+                state.last_stored_id.clone().map(|id| (stream.clone(), id))
+            })
+            .collect()
+    }
+}
+
+/// Reads the checkpoint for a stream and returns it if it is recent enough to resume from.
+pub async fn read_resume_id(redis_pool: &RedisPool, stream: &str) -> Result<Option<String>> {
+    let checkpoint: Option<String> = redis_pool
+        .hget(ENV_CONFIG.checkpoint_key.as_str(), stream)
+        .await
+        .map_err(metrics::redis_error("hget"))
+        .with_context(|| format!("failed to read checkpoint for stream {stream}"))?;
+
+    let checkpoint = match checkpoint {
+        Some(checkpoint) => checkpoint,
+        None => {
+            debug!(stream, "no checkpoint found");
+            return Ok(None);
+        }
+    };
+
+    let age_ms = stream_id_millis(&checkpoint).map(|millis| now_millis().saturating_sub(millis));
+    match age_ms {
+        Some(age_ms) if age_ms <= ENV_CONFIG.checkpoint_max_age_ms => {
+            info!(stream, checkpoint, age_ms, "resuming from checkpoint");
+            Ok(Some(checkpoint))
+        }
+        Some(age_ms) => {
+            info!(
+                stream,
+                checkpoint, age_ms, "checkpoint too old, starting from new submissions"
+            );
+            Ok(None)
+        }
+        None => {
+            warn!(
+                stream,
+                checkpoint, "checkpoint is not a valid stream id, ignoring it"
+            );
+            Ok(None)
+        }
+    }
+}
+
+async fn write_checkpoints(redis_pool: &RedisPool, ids: HashMap<String, String>) -> Result<()> {
+    redis_pool
+        .hset::<(), _, _>(ENV_CONFIG.checkpoint_key.as_str(), ids)
+        .await
+        .map_err(metrics::redis_error("hset"))
+        .context("failed to write checkpoints")
+}
+
+// Returns the checkpoints which changed since we last wrote them.
+fn changed_ids(
+    checkpoint: &Checkpoint,
+    last_written_ids: &HashMap<String, String>,
+) -> HashMap<String, String> {
+    checkpoint
+        .last_stored_ids()
+        .into_iter()
+        .filter(|(stream, id)| last_written_ids.get(stream) != Some(id))
+        .collect()
+}
+
+async fn write_checkpoint_periodically(
+    checkpoint: &Checkpoint,
+    redis_pool: &RedisPool,
+    last_written_ids: &mut HashMap<String, String>,
+) -> Result<()> {
+    let mut interval = interval(CHECKPOINT_WRITE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let changed_ids = changed_ids(checkpoint, last_written_ids);
+        if !changed_ids.is_empty() {
+            write_checkpoints(redis_pool, changed_ids.clone()).await?;
+            debug!(?changed_ids, "wrote checkpoints");
+            last_written_ids.extend(changed_ids);
+        }
+    }
+}
+
+/// Writes the checkpoint periodically, and once more on shutdown. When reading through a consumer
+/// group the group keeps track of our position, and this returns a handle that completes
+/// immediately.
+pub fn run_checkpoint_thread(
+    checkpoint: Checkpoint,
+    redis_pool: RedisPool,
+    shutdown_notify: Arc<Notify>,
+) -> JoinHandle<()> {
+    if ENV_CONFIG.consumer_group.is_some() {
+        trace!("consumer group configured, not starting checkpoint thread");
+        return tokio::spawn(async {});
+    }
+
+    info!("starting checkpoint thread");
+    tokio::spawn({
+        async move {
+            let mut last_written_ids = HashMap::new();
+            select! {
+                _ = shutdown_notify.notified().fuse() => {
+                    info!("received shutdown signal, writing final checkpoint");
+                },
+                result = write_checkpoint_periodically(&checkpoint, &redis_pool, &mut last_written_ids).fuse() => {
+                    if let Err(e) = result {
+                        error!(?e, "checkpoint thread hit error, exited");
+                        shutdown_notify.notify_waiters();
+                    }
+                }
+            }
+
+            let changed_ids = changed_ids(&checkpoint, &last_written_ids);
+            if !changed_ids.is_empty() {
+                match write_checkpoints(&redis_pool, changed_ids.clone()).await {
+                    Ok(()) => info!(?changed_ids, "wrote final checkpoints"),
+                    Err(e) => error!(?e, "failed to write final checkpoints"),
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stream_id_millis_test() {
+        assert_eq!(stream_id_millis("1695898323000-0"), Some(1695898323000));
+        assert_eq!(stream_id_millis("1695898323000-12"), Some(1695898323000));
+        assert_eq!(stream_id_millis("$"), None);
+    }
+
+    #[test]
+    fn parse_stream_id_test() {
+        assert_eq!(
+            parse_stream_id("1695898323000-12"),
+            Some((1695898323000, 12))
+        );
+        assert!(parse_stream_id("9-0") < parse_stream_id("10-0"));
+        assert_eq!(parse_stream_id("1695898323000"), None);
+    }
+
+    #[test]
+    fn checkpoint_only_advances_past_contiguous_done_ids() {
+        let checkpoint = Checkpoint::new();
+        checkpoint.track("stream", "1-0");
+        checkpoint.track("stream", "2-0");
+        checkpoint.track("stream", "3-0");
+
+        checkpoint.mark_done("stream", "2-0");
+        assert_eq!(checkpoint.last_stored_id("stream"), None);
+
+        checkpoint.mark_done("stream", "1-0");
+        assert_eq!(checkpoint.last_stored_id("stream"), Some("2-0".to_string()));
+
+        checkpoint.mark_done("stream", "3-0");
+        assert_eq!(checkpoint.last_stored_id("stream"), Some("3-0".to_string()));
+    }
+
+    #[test]
+    fn checkpoint_ignores_untracked_ids() {
+        let checkpoint = Checkpoint::new();
+        checkpoint.mark_done("stream", "1-0");
+        assert_eq!(checkpoint.last_stored_id("stream"), None);
+    }
+
+    #[test]
+    fn checkpoint_tracks_streams_independently() {
+        let checkpoint = Checkpoint::new();
+        checkpoint.track("stream_a", "1-0");
+        checkpoint.track("stream_b", "2-0");
+
+        checkpoint.mark_done("stream_b", "2-0");
+        assert_eq!(checkpoint.last_stored_id("stream_a"), None);
+        assert_eq!(
+            checkpoint.last_stored_id("stream_b"),
+            Some("2-0".to_string())
+        );
+    }
+
+    #[test]
+    fn changed_ids_skips_written_checkpoints() {
+        let checkpoint = Checkpoint::new();
+        checkpoint.track("stream_a", "1-0");
+        checkpoint.track("stream_b", "2-0");
+        checkpoint.mark_done("stream_a", "1-0");
+        checkpoint.mark_done("stream_b", "2-0");
+
+        let last_written_ids = HashMap::from([("stream_a".to_string(), "1-0".to_string())]);
+        let changed = changed_ids(&checkpoint, &last_written_ids);
+
+        assert_eq!(
+            changed,
+            HashMap::from([("stream_b".to_string(), "2-0".to_string())])
+        );
+    }
+}
@@ -0,0 +1,157 @@
+//! Backfill replays a range of a stream, e.g. to re-populate the payload keys after a Redis
+//! failover. Entries go through the same filtering and storage path as the live tail, which keeps
+//! running alongside.
+//!
+//! The range bounds are anything XRANGE accepts, a full stream ID, or a millisecond timestamp which
+//! Redis completes to the first or last ID in that millisecond.
+use anyhow::{bail, Context, Result};
+use fred::{pool::RedisPool, prelude::StreamsInterface};
+use futures::channel::mpsc::Sender;
+use tracing::info;
+
+use crate::{
+    checkpoint::parse_stream_id, env::ENV_CONFIG, health::RedisConsumerHealth, metrics,
+    performance::BlockCounter,
+};
+
+use super::{decode::XRangeBlockSubmissions, forward_submissions, StreamSubmission};
+
+// Max number of submissions to pull at a time.
+const BACKFILL_BATCH_SIZE: u64 = 128;
+
+// A range bound as the stream ID it covers up to, a millisecond timestamp covers the whole
+// millisecond.
+fn parse_bound(bound: &str, default_sequence: u64) -> Result<(u64, u64)> {
+    match (parse_stream_id(bound), bound.parse::<u64>()) {
+        (Some(id), _) => Ok(id),
+        (None, Ok(millis)) => Ok((millis, default_sequence)),
+        (None, Err(_)) => bail!("{bound} is not a stream ID or millisecond timestamp"),
+    }
+}
+
+/// Checks the range bounds are stream IDs or millisecond timestamps, and the start comes no later
+/// than the end. The start may be "-" and the end "+", the start and end of the stream.
+pub fn check_range(start: &str, end: Option<&str>) -> Result<()> {
+    let start_id = match start {
+        "-" => (0, 0),
+        start => parse_bound(start, 0).context("invalid backfill start")?,
+    };
+    let end_id = match end {
+        None | Some("+") => (u64::MAX, u64::MAX),
+        Some(end) => parse_bound(end, u64::MAX).context("invalid backfill end")?,
+    };
+    if start_id > end_id {
+        bail!("backfill start {start} comes after backfill end {end:?}");
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+struct BackfillTotals {
+    read: usize,
+    skipped: usize,
+}
+
+async fn backfill_stream(
+    block_counter: &BlockCounter,
+    redis_pool: &RedisPool,
+    redis_consumer_health: &RedisConsumerHealth,
+    stream: &str,
+    start: &str,
+    end: &str,
+    submissions_tx: &mut Sender<StreamSubmission>,
+) -> Result<BackfillTotals> {
+    let mut totals = BackfillTotals::default();
+    let mut cursor = start.to_string();
+
+    loop {
+        let submissions: XRangeBlockSubmissions = redis_pool
+            .xrange(stream, cursor.as_str(), end, Some(BACKFILL_BATCH_SIZE))
+            .await
+            .map_err(metrics::redis_error("xrange"))
+            .with_context(|| {
+                format!(
+                    "failed to read submissions range from stream {stream} starting at {cursor}"
+                )
+            })?;
+
+        let last_id = match submissions.0.last() {
+            Some((id, _value)) => id.clone(),
+            None => return Ok(totals),
+        };
+        let batch_len = submissions.0.len();
+
+        let skipped_ids = forward_submissions(
+            block_counter,
+            None,
+            redis_pool,
+            stream,
+            redis_consumer_health,
+            submissions.0,
+            submissions_tx,
+        )
+        .await?;
+
+        totals.read += batch_len;
+        totals.skipped += skipped_ids.len();
+        info!(
+            stream,
+            last_id,
+            read = totals.read,
+            skipped = totals.skipped,
+            "backfill progress"
+        );
+
+        // Continue right after the last ID we've seen.
+        cursor = format!("({last_id}");
+    }
+}
+
+pub async fn backfill_submissions(
+    block_counter: &BlockCounter,
+    redis_pool: &RedisPool,
+    redis_consumer_health: &RedisConsumerHealth,
+    start: &str,
+    end: &str,
+    mut submissions_tx: Sender<StreamSubmission>,
+) -> Result<()> {
+    for stream in ENV_CONFIG.stream_names.iter() {
+        info!(stream, start, end, "starting backfill");
+        let totals = backfill_stream(
+            block_counter,
+            redis_pool,
+            redis_consumer_health,
+            stream,
+            start,
+            end,
+            &mut submissions_tx,
+        )
+        .await?;
+        info!(
+            stream,
+            read = totals.read,
+            skipped = totals.skipped,
+            forwarded = totals.read - totals.skipped,
+            "finished backfill"
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_range_test() {
+        assert!(check_range("-", None).is_ok());
+        assert!(check_range("1000", Some("1000")).is_ok());
+        assert!(check_range("1000-3", Some("1000")).is_ok());
+        assert!(check_range("1000-0", Some("+")).is_ok());
+        assert!(check_range("2000", Some("1000-5")).is_err());
+        assert!(check_range("yesterday", None).is_err());
+        assert!(check_range("1000", Some("$")).is_err());
+    }
+}
@@ -0,0 +1,90 @@
+//! Stream entries we fail to decode are copied to a dead-letter stream, together with the error,
+//! so they can be inspected later without holding up the rest of the batch.
+use anyhow::{Context, Result};
+use fred::{
+    pool::RedisPool,
+    prelude::StreamsInterface,
+    types::{RedisKey, RedisValue},
+};
+use tracing::warn;
+
+use crate::{env::ENV_CONFIG, metrics};
+
+use super::decode::UndecodableSubmission;
+
+// The dead-letter stream is for inspection, we don't want it to grow without bound.
+const DEAD_LETTER_STREAM_MAX_LEN: i64 = 10_000;
+
+// The fields of the original entry, as far as we can make them out.
+fn raw_fields(raw: RedisValue) -> Vec<(RedisKey, RedisValue)> {
+    match raw {
+        RedisValue::Array(values) => {
+            let mut values = values.into_iter();
+            let mut fields = Vec::new();
+            while let (Some(key), Some(value)) = (values.next(), values.next()) {
+                if let Some(key) = key.into_string() {
+                    fields.push((key.into(), value));
+                }
+            }
+            fields
+        }
+        RedisValue::Map(map) => map.inner().into_iter().collect(),
+        _ => Vec::new(),
+    }
+}
+
+pub async fn send_to_dead_letter_stream(
+    redis_pool: &RedisPool,
+    stream: &str,
+    id: &str,
+    undecodable: UndecodableSubmission,
+) -> Result<()> {
+    warn!(stream, id, error = %undecodable.error, "failed to decode submission, sending to dead-letter stream");
+
+    let mut fields: Vec<(RedisKey, RedisValue)> = vec![
+        ("dlq_source_stream".into(), stream.into()),
+        ("dlq_source_id".into(), id.into()),
+        (
+            "dlq_error".into(),
+            undecodable.error.details().to_string().into(),
+        ),
+    ];
+    fields.extend(raw_fields(undecodable.raw));
+
+    redis_pool
+        .xadd::<(), _, _, _, _>(
+            ENV_CONFIG.dead_letter_stream.as_str(),
+            false,
+            ("MAXLEN", "~", DEAD_LETTER_STREAM_MAX_LEN),
+            "*",
+            fields,
+        )
+        .await
+        .map_err(metrics::redis_error("xadd"))
+        .with_context(|| format!("failed to send submission {id} to dead-letter stream"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_fields_from_array() {
+        let raw = RedisValue::Array(vec![
+            RedisValue::String("payload".into()),
+            RedisValue::String("not json".into()),
+            RedisValue::String("received_at".into()),
+            RedisValue::Integer(200),
+        ]);
+        let fields = raw_fields(raw);
+
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[0].0, "payload".into());
+        assert_eq!(fields[1].1, RedisValue::Integer(200));
+    }
+
+    #[test]
+    fn raw_fields_from_nil() {
+        assert!(raw_fields(RedisValue::Null).is_empty());
+    }
+}
@@ -4,65 +4,104 @@ use fred::{
 };
 use tracing::error;
 
-use crate::BlockSubmission;
+use crate::{fork::ForkSchedule, BlockSubmission};
 
 fn into_redis_parse_err(err: impl std::fmt::Display) -> RedisError {
     RedisError::new(RedisErrorKind::Parse, err.to_string())
 }
 
-pub struct XReadBlockSubmissions(pub Option<Vec<(String, BlockSubmission)>>);
+/// A stream message that could not be decoded into a block submission, with its raw fields.
+#[derive(Debug)]
+pub struct UndecodableSubmission {
+    pub error: RedisError,
+    pub raw: RedisValue,
+}
+
+/// A stream message key and the block submission it decoded into, if it did.
+pub type DecodedMessage = (String, Result<BlockSubmission, UndecodableSubmission>);
+
+/// Fails a decoded submission whose payload doesn't have the fields its fork expects, so it's
+/// dead-lettered like any other submission we can't decode.
+pub fn check_fork(
+    decoded: Result<BlockSubmission, UndecodableSubmission>,
+    fork_schedule: &ForkSchedule,
+) -> Result<BlockSubmission, UndecodableSubmission> {
+    let submission = decoded?;
+    match submission.check_fork(fork_schedule) {
+        Ok(()) => Ok(submission),
+        Err(error) => Err(UndecodableSubmission {
+            error: into_redis_parse_err(error),
+            raw: submission.into(),
+        }),
+    }
+}
+
+// Decodes an array of stream messages, each an array of the message key and the block submission.
+// A message which does not decode into a block submission does not fail the whole batch, it is
+// returned as an UndecodableSubmission instead.
+fn decode_messages(messages: Vec<RedisValue>) -> Result<Vec<DecodedMessage>, RedisError> {
+    messages
+        .into_iter()
+        .map(|key_value_pair| {
+            let mut key_value_pair_iter = key_value_pair.into_array().into_iter();
+            let key = key_value_pair_iter
+                .next()
+                .and_then(|key| key.into_string())
+                .ok_or_else(|| {
+                    into_redis_parse_err("expect first element in key value pair to be a key")
+                })?;
+            let value = key_value_pair_iter.next().ok_or_else(|| {
+                into_redis_parse_err(
+                    "expect second element in key value pair to be a block submission",
+                )
+            })?;
+
+            // Cloning is cheap, string values are reference counted bytes.
+            let value = value
+                .clone()
+                .convert::<BlockSubmission>()
+                .map_err(|error| UndecodableSubmission { error, raw: value });
+
+            Ok((key, value))
+        })
+        .collect()
+}
+
+/// The XREAD response, per stream the stream name and the messages read from it.
+pub struct XReadBlockSubmissions(pub Option<Vec<(String, Vec<DecodedMessage>)>>);
 
 impl FromRedis for XReadBlockSubmissions {
     fn from_value(value: RedisValue) -> Result<Self, RedisError> {
         match value {
             RedisValue::Null => Ok(XReadBlockSubmissions(None)),
-            RedisValue::Array(streams) => {
-                let first_stream = streams.into_iter().next().ok_or_else(|| {
-                    into_redis_parse_err(
-                        "expect first element in streams array to be the submissions stream",
-                    )
-                })?;
-
-                // The stream is an array of two elements. The first is the stream name, the second
-                // is an array of messages.
-                let mut submissions_stream = first_stream.into_array().into_iter();
-                let _name = submissions_stream.next();
-                let key_value_pairs = submissions_stream
-                    .next()
-                    .ok_or_else(|| {
-                        into_redis_parse_err(
-                            "expect second element in the submissions stream array to be a RedisValue::Array with pairs of message keys and values",
-                        )
-                    })?
-                    .into_array();
-
-                key_value_pairs
-                    .into_iter()
-                    .map(|key_value_pair| {
-                        let mut key_value_pair_iter = key_value_pair.into_array().into_iter();
-                        let key = key_value_pair_iter
-                            .next()
-                            .and_then(|key| key.into_string())
-                            .ok_or_else(|| {
-                                into_redis_parse_err(
-                                    "expect first element in key value pair to be a key",
-                                )
-                            })?;
-                        let value = key_value_pair_iter
-                            .next()
-                            .ok_or_else(|| {
-                                into_redis_parse_err(
-                                "expect second element in key value pair to be a block submission",
+            RedisValue::Array(streams) => streams
+                .into_iter()
+                .map(|stream| {
+                    // Each stream is an array of two elements. The first is the stream name, the
+                    // second is an array of messages.
+                    let mut submissions_stream = stream.into_array().into_iter();
+                    let name = submissions_stream
+                        .next()
+                        .and_then(|name| name.into_string())
+                        .ok_or_else(|| {
+                            into_redis_parse_err(
+                                "expect first element in the submissions stream array to be the stream name",
                             )
-                            })?
-                            .convert::<BlockSubmission>()?;
-
-                        Ok((key, value))
-                    })
-                    .collect::<Result<Vec<_>, RedisError>>()
-                    .map(Some)
-                    .map(XReadBlockSubmissions)
-            }
+                        })?;
+                    let key_value_pairs = submissions_stream
+                        .next()
+                        .ok_or_else(|| {
+                            into_redis_parse_err(
+                                "expect second element in the submissions stream array to be a RedisValue::Array with pairs of message keys and values",
+                            )
+                        })?
+                        .into_array();
+
+                    Ok((name, decode_messages(key_value_pairs)?))
+                })
+                .collect::<Result<Vec<_>, RedisError>>()
+                .map(Some)
+                .map(XReadBlockSubmissions),
             response => {
                 error!(response=?response, "expect XREAD response to be nil or an array");
                 Err(into_redis_parse_err(
@@ -73,6 +112,49 @@ impl FromRedis for XReadBlockSubmissions {
     }
 }
 
+/// The XRANGE response, the messages in the requested range.
+pub struct XRangeBlockSubmissions(pub Vec<DecodedMessage>);
+
+impl FromRedis for XRangeBlockSubmissions {
+    fn from_value(value: RedisValue) -> Result<Self, RedisError> {
+        match value {
+            RedisValue::Null => Ok(XRangeBlockSubmissions(Vec::new())),
+            RedisValue::Array(messages) => decode_messages(messages).map(XRangeBlockSubmissions),
+            response => {
+                error!(response=?response, "expect XRANGE response to be nil or an array");
+                Err(into_redis_parse_err(
+                    "expect XRANGE response to be nil or an array",
+                ))
+            }
+        }
+    }
+}
+
+/// The XCLAIM response, the claimed messages.
+pub struct XClaimBlockSubmissions(pub Vec<DecodedMessage>);
+
+impl FromRedis for XClaimBlockSubmissions {
+    fn from_value(value: RedisValue) -> Result<Self, RedisError> {
+        match value {
+            RedisValue::Null => Ok(XClaimBlockSubmissions(Vec::new())),
+            // Before Redis 7, entries deleted from the stream are claimed as nil.
+            RedisValue::Array(messages) => decode_messages(
+                messages
+                    .into_iter()
+                    .filter(|message| !message.is_null())
+                    .collect(),
+            )
+            .map(XClaimBlockSubmissions),
+            response => {
+                error!(response=?response, "expect XCLAIM response to be nil or an array");
+                Err(into_redis_parse_err(
+                    "expect XCLAIM response to be nil or an array",
+                ))
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -94,6 +176,25 @@ mod tests {
         ])])
     }
 
+    #[test]
+    fn check_fork_rejects_payloads_missing_fork_fields() {
+        let capella = ForkSchedule {
+            capella_epoch: 0,
+            deneb_epoch: None,
+            electra_epoch: None,
+        };
+        let deneb = ForkSchedule {
+            deneb_epoch: Some(0),
+            ..capella.clone()
+        };
+
+        assert!(check_fork(Ok(BlockSubmission::default()), &capella).is_ok());
+
+        // The default payload has no blobs bundle, which Deneb requires.
+        let undecodable = check_fork(Ok(BlockSubmission::default()), &deneb).unwrap_err();
+        assert!(matches!(undecodable.raw, RedisValue::Map(_)));
+    }
+
     #[test]
     fn from_redis_value_null() {
         let value = RedisValue::Null;
@@ -107,13 +208,85 @@ mod tests {
         let x_read_response = XReadBlockSubmissions::from_value(value).unwrap();
 
         assert!(x_read_response.0.is_some());
-        let messages = x_read_response.0.unwrap();
+        let streams = x_read_response.0.unwrap();
 
+        assert_eq!(streams.len(), 1);
+        let (ref name, ref messages) = streams[0];
+        assert_eq!(name, "stream_name");
         assert_eq!(messages.len(), 1);
         let (ref key, ref _block_submission) = messages[0];
         assert_eq!(key, "key");
     }
 
+    #[test]
+    fn from_redis_value_undecodable_submission() {
+        let value = RedisValue::Array(vec![RedisValue::Array(vec![
+            RedisValue::String("stream_name".into()),
+            RedisValue::Array(vec![
+                RedisValue::Array(vec![
+                    RedisValue::String("bad_key".into()),
+                    RedisValue::Array(vec![
+                        RedisValue::String("payload".into()),
+                        RedisValue::String("not json".into()),
+                    ]),
+                ]),
+                RedisValue::Array(vec![
+                    RedisValue::String("good_key".into()),
+                    BlockSubmission::default().into(),
+                ]),
+            ]),
+        ])]);
+        let streams = XReadBlockSubmissions::from_value(value).unwrap().0.unwrap();
+        let messages = &streams[0].1;
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].0, "bad_key");
+        assert!(messages[0].1.is_err());
+        assert_eq!(messages[1].0, "good_key");
+        assert!(messages[1].1.is_ok());
+    }
+
+    #[test]
+    fn from_redis_value_empty_pending_list() {
+        // XREADGROUP for pending entries returns the stream with an empty array once none are
+        // left.
+        let value = RedisValue::Array(vec![RedisValue::Array(vec![
+            RedisValue::String("stream_name".into()),
+            RedisValue::Array(vec![]),
+        ])]);
+        let streams = XReadBlockSubmissions::from_value(value).unwrap().0.unwrap();
+
+        assert_eq!(streams.len(), 1);
+        assert!(streams[0].1.is_empty());
+    }
+
+    #[test]
+    fn from_redis_value_multiple_streams() {
+        let value = RedisValue::Array(vec![
+            RedisValue::Array(vec![
+                RedisValue::String("stream_a".into()),
+                RedisValue::Array(vec![RedisValue::Array(vec![
+                    RedisValue::String("key_a".into()),
+                    BlockSubmission::default().into(),
+                ])]),
+            ]),
+            RedisValue::Array(vec![
+                RedisValue::String("stream_b".into()),
+                RedisValue::Array(vec![RedisValue::Array(vec![
+                    RedisValue::String("key_b".into()),
+                    BlockSubmission::default().into(),
+                ])]),
+            ]),
+        ]);
+        let streams = XReadBlockSubmissions::from_value(value).unwrap().0.unwrap();
+
+        assert_eq!(streams.len(), 2);
+        assert_eq!(streams[0].0, "stream_a");
+        assert_eq!(streams[0].1[0].0, "key_a");
+        assert_eq!(streams[1].0, "stream_b");
+        assert_eq!(streams[1].1[0].0, "key_b");
+    }
+
     #[test]
     fn from_redis_value_invalid_array() {
         let value = mock_invalid_redis_value();
@@ -121,4 +294,37 @@ mod tests {
 
         assert!(result.is_err());
     }
+
+    #[test]
+    fn xrange_from_redis_value() {
+        let value = RedisValue::Array(vec![
+            RedisValue::Array(vec![
+                RedisValue::String("1-0".into()),
+                BlockSubmission::default().into(),
+            ]),
+            RedisValue::Array(vec![
+                RedisValue::String("2-0".into()),
+                BlockSubmission::default().into(),
+            ]),
+        ]);
+        let messages = XRangeBlockSubmissions::from_value(value).unwrap().0;
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].0, "2-0");
+    }
+
+    #[test]
+    fn xclaim_from_redis_value() {
+        let value = RedisValue::Array(vec![
+            RedisValue::Array(vec![
+                RedisValue::String("key".into()),
+                BlockSubmission::default().into(),
+            ]),
+            RedisValue::Null,
+        ]);
+        let messages = XClaimBlockSubmissions::from_value(value).unwrap().0;
+
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].0, "key");
+    }
 }
@@ -0,0 +1,49 @@
+//! Consumer group support. When a consumer group is configured, replicas split the stream between
+//! them, and entries are only acknowledged once they have been stored. Anything read but not yet
+//! acknowledged stays on the group's pending entries list, so a restart can pick up where it left
+//! off.
+use anyhow::{Context, Result};
+use fred::{pool::RedisPool, prelude::StreamsInterface};
+use tracing::debug;
+
+use crate::metrics;
+
+/// Create the consumer group on a stream, and the stream if it does not exist yet. A new group
+/// starts reading from new entries only.
+pub async fn ensure_group_exists(redis_pool: &RedisPool, stream: &str, group: &str) -> Result<()> {
+    match redis_pool
+        .xgroup_create::<(), _, _, _>(stream, group, "$", true)
+        .await
+    {
+        Ok(()) => {
+            debug!(stream, group, "created consumer group");
+            Ok(())
+        }
+        Err(e) if e.details().starts_with("BUSYGROUP") => {
+            debug!(stream, group, "consumer group already exists");
+            Ok(())
+        }
+        Err(e) => Err(e)
+            .with_context(|| format!("failed to create consumer group {group} on stream {stream}")),
+    }
+}
+
+/// Remove entries from the pending entries list of the group.
+pub async fn ack(
+    redis_pool: &RedisPool,
+    stream: &str,
+    group: &str,
+    ids: Vec<String>,
+) -> Result<()> {
+    if ids.is_empty() {
+        return Ok(());
+    }
+
+    redis_pool
+        .xack::<(), _, _, _>(stream, group, ids)
+        .await
+        .map_err(metrics::redis_error("xack"))
+        .with_context(|| {
+            format!("failed to ack submissions on stream {stream} for consumer group {group}")
+        })
+}
@@ -1,20 +1,34 @@
 //! # Consumer
 //!
-//! Consumes block submissions received by the relay from a Redis stream and stores them in that
-//! same Redis by BlockSubmissionKey.
-use std::sync::Arc;
+//! Consumes block submissions received by the relay from one or more Redis streams and stores them
+//! in that same Redis by BlockSubmissionKey.
+use std::{collections::HashMap, sync::Arc};
 
 use anyhow::{Context, Result};
-use fred::{pool::RedisPool, prelude::StreamsInterface};
+use fred::{pool::RedisPool, prelude::StreamsInterface, types::RedisValue};
 use futures::{channel::mpsc::Sender, select, FutureExt, SinkExt};
 use tokio::{sync::Notify, task::JoinHandle};
 use tracing::{debug, error, info, trace};
 
-use crate::{health::RedisConsumerHealth, BlockSubmission, STREAM_NAME};
+use crate::{
+    checkpoint::{self, Checkpoint},
+    env::{self, ENV_CONFIG},
+    filter::FILTER_POLICY,
+    health::{RedisConsumerHealth, TaskHealth},
+    metrics::{self, CHANNEL_DEPTH, DECODE_FAILURES, SUBMISSIONS_READ, SUBMISSIONS_SKIPPED},
+    performance::BlockCounter,
+    slot_clock::SLOT_CLOCK,
+    BlockSubmission,
+};
 
-use self::decode::XReadBlockSubmissions;
+use self::decode::{DecodedMessage, XReadBlockSubmissions};
 
+pub mod backfill;
+mod dead_letter;
 mod decode;
+pub mod group;
+mod reclaim;
+mod stream_info;
 
 // To avoid busywaiting on the Redis stream, we use Redis' block option to allow Redis to wait up
 // to READ_SUBMISSIONS_BLOCK_DURATION milliseconds with responding if no new submissions are
@@ -24,41 +38,191 @@ const READ_SUBMISSIONS_BLOCK_MS: u64 = 1000;
 // Max number of new submissions to pull at a time.
 const SUBMISSIONS_BATCH_SIZE: u64 = 128;
 
+/// A block submission together with the stream, and the ID of the stream entry, it was read from.
+#[derive(Debug)]
+pub struct StreamSubmission {
+    pub id: String,
+    /// Stale submissions are only passed on to be archived, they are not stored for the relay.
+    pub stale: bool,
+    pub stream: String,
+    pub submission: BlockSubmission,
+}
+
+// When we fall behind, submissions for slots that have long been proposed are no use to the relay.
+fn is_stale(slot: u64, current_slot: Option<u64>, stale_slots: u64) -> bool {
+    current_slot.is_some_and(|current_slot| current_slot.saturating_sub(slot) > stale_slots)
+}
+
+// Feeds the submissions accepted by the filter policy to the submissions channel. Submissions we
+// failed to decode go to the dead-letter stream. Stale submissions are skipped, or passed on marked
+// stale when we archive them. Returns the IDs of the submissions that were skipped or
+// dead-lettered. When a checkpoint is passed, every ID is tracked, and skipped IDs are marked done
+// right away.
+async fn forward_submissions(
+    block_counter: &BlockCounter,
+    checkpoint: Option<&Checkpoint>,
+    redis_pool: &RedisPool,
+    stream: &str,
+    redis_consumer_health: &RedisConsumerHealth,
+    submissions: Vec<DecodedMessage>,
+    submissions_tx: &mut Sender<StreamSubmission>,
+) -> Result<Vec<String>> {
+    let submissions_len = submissions.len();
+    let mut skipped_ids = Vec::new();
+    SUBMISSIONS_READ
+        .with_label_values(&[stream])
+        .inc_by(submissions_len as u64);
+
+    for (id, value) in submissions {
+        if let Some(checkpoint) = checkpoint {
+            checkpoint.track(stream, &id);
+        }
+
+        let value = match decode::check_fork(value, &ENV_CONFIG.network.fork_schedule) {
+            Ok(value) => value,
+            Err(undecodable) => {
+                dead_letter::send_to_dead_letter_stream(redis_pool, stream, &id, undecodable)
+                    .await?;
+                redis_consumer_health.increment_dead_lettered();
+                DECODE_FAILURES.with_label_values(&[stream]).inc();
+                block_counter.record_read(None);
+                block_counter.record_skipped(None);
+                if let Some(checkpoint) = checkpoint {
+                    checkpoint.mark_done(stream, &id);
+                }
+                redis_consumer_health.set_last_processed_id(stream, &id);
+                skipped_ids.push(id);
+                continue;
+            }
+        };
+
+        trace!(stream, ?value, "read new submission from redis");
+
+        let slot = value.message().slot;
+        block_counter.record_read(Some(slot));
+
+        let stale = ENV_CONFIG
+            .stale_submission_slots
+            .is_some_and(|stale_slots| is_stale(slot, SLOT_CLOCK.current_slot(), stale_slots));
+        if stale {
+            redis_consumer_health.increment_stale();
+            if !ENV_CONFIG.archive_stale_submissions {
+                trace!(stream, ?value, "skipping stale submission");
+                SUBMISSIONS_SKIPPED
+                    .with_label_values(&[stream, "stale"])
+                    .inc();
+                block_counter.record_skipped(Some(slot));
+                if let Some(checkpoint) = checkpoint {
+                    checkpoint.mark_done(stream, &id);
+                }
+                redis_consumer_health.set_last_processed_id(stream, &id);
+                skipped_ids.push(id);
+                continue;
+            }
+        }
+
+        if let Err(rejection) = FILTER_POLICY.evaluate(&value) {
+            trace!(
+                stream,
+                ?value,
+                predicate = %rejection,
+                "skipping submission because the filter policy rejected it"
+            );
+            SUBMISSIONS_SKIPPED
+                .with_label_values(&[stream, "filter_policy"])
+                .inc();
+            block_counter.record_skipped(Some(slot));
+            if let Some(checkpoint) = checkpoint {
+                checkpoint.mark_done(stream, &id);
+            }
+            redis_consumer_health.set_last_processed_id(stream, &id);
+            skipped_ids.push(id);
+            continue;
+        }
+
+        submissions_tx
+            .feed(StreamSubmission {
+                id,
+                stale,
+                stream: stream.to_string(),
+                submission: value,
+            })
+            .await
+            .context("failed to feed a new submission to submissions channel")?;
+        CHANNEL_DEPTH.inc();
+    }
+    submissions_tx
+        .flush()
+        .await
+        .context("failed to flush the submissions channel")?;
+
+    debug!(
+        stream,
+        count = submissions_len,
+        "read new submissions from redis",
+    );
+
+    Ok(skipped_ids)
+}
+
+// The ID of the newest entry in a stream, or "0-0" when the stream is empty or missing. We use it
+// rather than "$", which Redis resolves anew on every XREAD, so entries arriving on a quiet stream
+// in between reads are not missed.
+async fn latest_id(redis_pool: &RedisPool, stream: &str) -> Result<String> {
+    let entries: Vec<(String, HashMap<String, RedisValue>)> = redis_pool
+        .xrevrange_values(stream, "+", "-", Some(1))
+        .await
+        .map_err(metrics::redis_error("xrevrange"))
+        .with_context(|| format!("failed to read latest id of stream {stream}"))?;
+
+    Ok(entries
+        .into_iter()
+        .next()
+        .map(|(id, _fields)| id)
+        .unwrap_or_else(|| "0-0".to_string()))
+}
+
 async fn add_new_submissions_loop(
+    block_counter: &BlockCounter,
+    checkpoint: &Checkpoint,
     redis_pool: &RedisPool,
     redis_consumer_health: &RedisConsumerHealth,
-    mut submissions_tx: Sender<BlockSubmission>,
+    mut submissions_tx: Sender<StreamSubmission>,
 ) -> Result<()> {
-    let mut last_id_seen: Option<String> = None;
+    // Every stream is read from its own last seen ID.
+    let mut last_ids_seen: HashMap<String, String> = HashMap::new();
+    for stream in ENV_CONFIG.stream_names.iter() {
+        let start_id = match checkpoint::read_resume_id(redis_pool, stream).await? {
+            Some(id) => id,
+            None => latest_id(redis_pool, stream).await?,
+        };
+        debug!(stream, start_id, "reading submissions");
+        redis_consumer_health.set_last_processed_id(stream, &start_id);
+        last_ids_seen.insert(stream.clone(), start_id);
+    }
 
     loop {
-        let block_submissions: XReadBlockSubmissions = {
-            match last_id_seen.as_ref() {
-                Some(last_id_seen) => redis_pool
-                    .xread(
-                        Some(SUBMISSIONS_BATCH_SIZE),
-                        Some(READ_SUBMISSIONS_BLOCK_MS),
-                        STREAM_NAME,
-                        last_id_seen,
-                    )
-                    .await
-                    .with_context(|| {
-                        format!(
-                            "failed to read submissions from redis using starting id: {}",
-                            last_id_seen
-                        )
-                    }),
-                None => redis_pool
-                    .xread(
-                        Some(SUBMISSIONS_BATCH_SIZE),
-                        Some(READ_SUBMISSIONS_BLOCK_MS),
-                        STREAM_NAME,
-                        "$",
-                    )
-                    .await
-                    .context("failed to read submissions from redis using starting id: $"),
-            }
-        }?;
+        let ids: Vec<&str> = ENV_CONFIG
+            .stream_names
+            .iter()
+            .map(|stream| last_ids_seen[stream].as_str())
+            .collect();
+
+        let block_submissions: XReadBlockSubmissions = redis_pool
+            .xread(
+                Some(SUBMISSIONS_BATCH_SIZE),
+                Some(READ_SUBMISSIONS_BLOCK_MS),
+                ENV_CONFIG.stream_names.clone(),
+                ids.clone(),
+            )
+            .await
+            .map_err(metrics::redis_error("xread"))
+            .with_context(|| {
+                format!(
+                    "failed to read submissions from redis using starting ids: {:?}",
+                    ids
+                )
+            })?;
 
         match block_submissions.0 {
             None => {
@@ -68,57 +232,181 @@ async fn add_new_submissions_loop(
                     READ_SUBMISSIONS_BLOCK_MS / 1000
                 );
             }
-            Some(submissions) => {
-                // Update the last id seen.
-                last_id_seen = submissions.last().map(|(key, _value)| key.clone());
-
-                let submissions_len = submissions.len();
-
-                for (_key, value) in submissions {
-                    trace!(?value, "read new submission from redis");
+            Some(streams) => {
+                redis_consumer_health.set_last_message_received_now();
 
-                    if !value.safe_to_propose() {
-                        trace!(
-                            ?value,
-                            "skipping submission because it is not safe to store"
-                        );
-                        continue;
+                for (stream, submissions) in streams {
+                    // Update the last id seen.
+                    if let Some((key, _value)) = submissions.last() {
+                        last_ids_seen.insert(stream.clone(), key.clone());
                     }
 
-                    submissions_tx
-                        .feed(value)
-                        .await
-                        .context("failed to feed a new submission to submissions channel")?;
+                    forward_submissions(
+                        block_counter,
+                        Some(checkpoint),
+                        redis_pool,
+                        &stream,
+                        redis_consumer_health,
+                        submissions,
+                        &mut submissions_tx,
+                    )
+                    .await?;
                 }
-                submissions_tx
-                    .flush()
-                    .await
-                    .context("failed to flush the submissions channel")?;
+            }
+        }
+    }
+}
 
-                redis_consumer_health.set_last_message_received_now();
+async fn add_new_submissions_group_loop(
+    block_counter: &BlockCounter,
+    redis_pool: &RedisPool,
+    redis_consumer_health: &RedisConsumerHealth,
+    group: &str,
+    mut submissions_tx: Sender<StreamSubmission>,
+) -> Result<()> {
+    for stream in ENV_CONFIG.stream_names.iter() {
+        group::ensure_group_exists(redis_pool, stream, group).await?;
+    }
+    let consumer = env::replica_name();
+
+    info!(
+        group,
+        consumer, "reading submissions through consumer group"
+    );
+
+    // We start by reading our own pending entries, those we read before a restart but never
+    // acknowledged. Once those run out for a stream we switch to ">" for it, meaning entries never
+    // delivered to any consumer in the group.
+    let mut pending_cursors: HashMap<String, Option<String>> = ENV_CONFIG
+        .stream_names
+        .iter()
+        .map(|stream| (stream.clone(), Some("0".to_string())))
+        .collect();
 
-                debug!(count = submissions_len, "read new submissions from redis",);
+    loop {
+        let ids: Vec<&str> = ENV_CONFIG
+            .stream_names
+            .iter()
+            .map(|stream| pending_cursors[stream].as_deref().unwrap_or(">"))
+            .collect();
+
+        let block_submissions: XReadBlockSubmissions = redis_pool
+            .xreadgroup(
+                group,
+                consumer.as_str(),
+                Some(SUBMISSIONS_BATCH_SIZE),
+                Some(READ_SUBMISSIONS_BLOCK_MS),
+                false,
+                ENV_CONFIG.stream_names.clone(),
+                ids.clone(),
+            )
+            .await
+            .map_err(metrics::redis_error("xreadgroup"))
+            .with_context(|| {
+                format!("failed to read submissions from consumer group using ids: {ids:?}")
+            })?;
+
+        let streams = match block_submissions.0 {
+            None => {
+                trace!(
+                    "no new submissions, asking again with {}s block",
+                    READ_SUBMISSIONS_BLOCK_MS / 1000
+                );
+                continue;
+            }
+            Some(streams) => streams,
+        };
+
+        redis_consumer_health.set_last_message_received_now();
+
+        for (stream, submissions) in streams {
+            let pending_cursor = pending_cursors
+                .get_mut(&stream)
+                .with_context(|| format!("read submissions from unexpected stream {stream}"))?;
+
+            if submissions.is_empty() {
+                if pending_cursor.take().is_some() {
+                    debug!(
+                        stream,
+                        "done reading pending submissions, reading new submissions"
+                    );
+                }
+                continue;
             }
+
+            if pending_cursor.is_some() {
+                *pending_cursor = submissions.last().map(|(key, _value)| key.clone());
+            }
+
+            let skipped_ids = forward_submissions(
+                block_counter,
+                None,
+                redis_pool,
+                &stream,
+                redis_consumer_health,
+                submissions,
+                &mut submissions_tx,
+            )
+            .await?;
+
+            // Skipped submissions will never reach storage, we acknowledge them here so they
+            // don't stay pending forever.
+            group::ack(redis_pool, &stream, group, skipped_ids).await?;
+        }
+    }
+}
+
+async fn add_new_submissions(
+    block_counter: &BlockCounter,
+    checkpoint: &Checkpoint,
+    redis_pool: &RedisPool,
+    redis_consumer_health: &RedisConsumerHealth,
+    submissions_tx: Sender<StreamSubmission>,
+) -> Result<()> {
+    match ENV_CONFIG.consumer_group.as_deref() {
+        Some(group) => {
+            add_new_submissions_group_loop(
+                block_counter,
+                redis_pool,
+                redis_consumer_health,
+                group,
+                submissions_tx,
+            )
+            .await
+        }
+        None => {
+            add_new_submissions_loop(
+                block_counter,
+                checkpoint,
+                redis_pool,
+                redis_consumer_health,
+                submissions_tx,
+            )
+            .await
         }
     }
 }
 
 pub fn run_consume_submissions_thread(
+    block_counter: Arc<BlockCounter>,
+    checkpoint: Checkpoint,
+    consumer_task_health: TaskHealth,
     redis_consumer_health: RedisConsumerHealth,
     redis_pool: RedisPool,
     shutdown_notify: Arc<Notify>,
-    submissions_tx: Sender<BlockSubmission>,
+    submissions_tx: Sender<StreamSubmission>,
 ) -> JoinHandle<()> {
     info!("starting cache submissions thread");
     tokio::spawn({
         async move {
+            let _running = consumer_task_health.start();
             // If another thread (e.g. server thread) would hit an error, we would have no way of
             // knowing and keep running. We use a shutdown_notify channel to signal to shut down.
             select! {
                 _ = shutdown_notify.notified().fuse() => {
                     info!("received shutdown signal, shutting down cache submissions thread");
                 },
-                result = add_new_submissions_loop(&redis_pool, &redis_consumer_health, submissions_tx).fuse() => {
+                result = add_new_submissions(&block_counter, &checkpoint, &redis_pool, &redis_consumer_health, submissions_tx).fuse() => {
                     match result {
                         Ok(()) => {
                             error!("add new submissions thread exited unexpectedly without error");
@@ -133,3 +421,123 @@ pub fn run_consume_submissions_thread(
         }
     })
 }
+
+/// Runs the reclaimer when reading through a consumer group, otherwise returns a handle that
+/// completes immediately.
+pub fn run_reclaim_submissions_thread(
+    block_counter: Arc<BlockCounter>,
+    redis_consumer_health: RedisConsumerHealth,
+    redis_pool: RedisPool,
+    shutdown_notify: Arc<Notify>,
+    submissions_tx: Sender<StreamSubmission>,
+) -> JoinHandle<()> {
+    let group = match ENV_CONFIG.consumer_group.clone() {
+        Some(group) => group,
+        None => {
+            trace!("no consumer group configured, not starting reclaim submissions thread");
+            return tokio::spawn(async {});
+        }
+    };
+
+    info!("starting reclaim submissions thread");
+    tokio::spawn({
+        async move {
+            select! {
+                _ = shutdown_notify.notified().fuse() => {
+                    info!("received shutdown signal, shutting down reclaim submissions thread");
+                },
+                result = reclaim::reclaim_submissions_loop(&block_counter, &redis_pool, &redis_consumer_health, &group, submissions_tx).fuse() => {
+                    match result {
+                        Ok(()) => {
+                            error!("reclaim submissions thread exited unexpectedly without error");
+                        },
+                        Err(e) => {
+                            error!(?e, "reclaim submissions thread hit error, exited");
+                            shutdown_notify.notify_waiters();
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Runs a backfill when a backfill start is configured, otherwise returns a handle that completes
+/// immediately. The thread exits once the range has been replayed. Backfilling is a side task, when
+/// it fails only the backfill stops, the live tail keeps running.
+pub fn run_backfill_submissions_thread(
+    block_counter: Arc<BlockCounter>,
+    redis_consumer_health: RedisConsumerHealth,
+    redis_pool: RedisPool,
+    shutdown_notify: Arc<Notify>,
+    submissions_tx: Sender<StreamSubmission>,
+) -> JoinHandle<()> {
+    let start = match ENV_CONFIG.backfill_start.clone() {
+        Some(start) => start,
+        None => {
+            trace!("no backfill start configured, not starting backfill submissions thread");
+            return tokio::spawn(async {});
+        }
+    };
+    let end = ENV_CONFIG
+        .backfill_end
+        .clone()
+        .unwrap_or_else(|| "+".to_string());
+
+    info!("starting backfill submissions thread");
+    tokio::spawn({
+        async move {
+            select! {
+                _ = shutdown_notify.notified().fuse() => {
+                    info!("received shutdown signal, shutting down backfill submissions thread");
+                },
+                result = backfill::backfill_submissions(&block_counter, &redis_pool, &redis_consumer_health, &start, &end, submissions_tx).fuse() => {
+                    match result {
+                        Ok(()) => {
+                            info!("backfill submissions thread finished");
+                        },
+                        Err(e) => {
+                            error!(?e, "backfill submissions thread hit error, exited");
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Keeps the stream length and consumer lag in the health state and metrics up to date.
+pub fn run_stream_info_thread(
+    redis_consumer_health: RedisConsumerHealth,
+    redis_pool: RedisPool,
+    shutdown_notify: Arc<Notify>,
+) -> JoinHandle<()> {
+    info!("starting stream info thread");
+    tokio::spawn({
+        async move {
+            select! {
+                _ = shutdown_notify.notified().fuse() => {
+                    info!("received shutdown signal, shutting down stream info thread");
+                },
+                _ = stream_info::update_stream_info_loop(&redis_pool, &redis_consumer_health).fuse() => {
+                    error!("stream info thread exited unexpectedly");
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_stale_test() {
+        assert!(!is_stale(100, Some(100), 2));
+        assert!(!is_stale(98, Some(100), 2));
+        assert!(is_stale(97, Some(100), 2));
+        // Bids for upcoming slots are never stale.
+        assert!(!is_stale(101, Some(100), 0));
+        assert!(!is_stale(0, None, 0));
+    }
+}
@@ -0,0 +1,151 @@
+//! Entries read through the consumer group stay pending until stored and acknowledged. When a
+//! consumer dies mid-batch nobody would ever acknowledge them. The reclaimer periodically claims
+//! other consumers' entries that have been idle for too long and feeds them back through the
+//! submissions channel.
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use fred::{pool::RedisPool, prelude::StreamsInterface};
+use futures::channel::mpsc::Sender;
+use tokio::time::interval;
+use tracing::{debug, info};
+
+use crate::{
+    env::{self, ENV_CONFIG},
+    health::RedisConsumerHealth,
+    metrics,
+    performance::BlockCounter,
+};
+
+use super::{decode::XClaimBlockSubmissions, forward_submissions, group, StreamSubmission};
+
+// How often we look for idle pending entries.
+const RECLAIM_INTERVAL: Duration = Duration::from_secs(4);
+
+// Max number of pending entries to claim at a time.
+const RECLAIM_BATCH_SIZE: u64 = 128;
+
+// Claims the entries other consumers left idle for longer than the configured threshold, paging
+// through the pending entries list. Our own pending entries may just be queued behind a slow
+// store, claiming those would feed them in a second time.
+async fn reclaim_idle_submissions(
+    block_counter: &BlockCounter,
+    redis_pool: &RedisPool,
+    redis_consumer_health: &RedisConsumerHealth,
+    stream: &str,
+    group: &str,
+    consumer: &str,
+    submissions_tx: &mut Sender<StreamSubmission>,
+) -> Result<()> {
+    let mut start = "-".to_string();
+
+    loop {
+        // Each pending entry is its ID, the consumer owning it, its idle time and delivery count.
+        let pending: Vec<(String, String, u64, u64)> = redis_pool
+            .xpending(
+                stream,
+                group,
+                (
+                    ENV_CONFIG.reclaim_min_idle_ms,
+                    start.as_str(),
+                    "+",
+                    RECLAIM_BATCH_SIZE,
+                ),
+            )
+            .await
+            .map_err(metrics::redis_error("xpending"))
+            .with_context(|| {
+                format!("failed to get idle pending submissions on stream {stream} from {start}")
+            })?;
+
+        let Some((last_id, ..)) = pending.last() else {
+            return Ok(());
+        };
+        start = format!("({last_id}");
+        let last_page = pending.len() < RECLAIM_BATCH_SIZE as usize;
+
+        let ids: Vec<String> = pending
+            .into_iter()
+            .filter(|(_id, owner, ..)| owner != consumer)
+            .map(|(id, ..)| id)
+            .collect();
+
+        // Claiming checks the idle time again, an entry its owner got to in the meantime stays.
+        if !ids.is_empty() {
+            let claimed: XClaimBlockSubmissions = redis_pool
+                .xclaim(
+                    stream,
+                    group,
+                    consumer,
+                    ENV_CONFIG.reclaim_min_idle_ms,
+                    ids,
+                    None,
+                    None,
+                    None,
+                    false,
+                    false,
+                )
+                .await
+                .map_err(metrics::redis_error("xclaim"))
+                .with_context(|| format!("failed to claim submissions on stream {stream}"))?;
+
+            if !claimed.0.is_empty() {
+                let claimed_len = claimed.0.len();
+                info!(
+                    stream,
+                    count = claimed_len,
+                    "reclaimed idle pending submissions"
+                );
+                redis_consumer_health.add_reclaimed(claimed_len as u64);
+                redis_consumer_health.set_last_message_received_now();
+
+                let skipped_ids = forward_submissions(
+                    block_counter,
+                    None,
+                    redis_pool,
+                    stream,
+                    redis_consumer_health,
+                    claimed.0,
+                    submissions_tx,
+                )
+                .await?;
+                group::ack(redis_pool, stream, group, skipped_ids).await?;
+            }
+        }
+
+        if last_page {
+            return Ok(());
+        }
+    }
+}
+
+pub async fn reclaim_submissions_loop(
+    block_counter: &BlockCounter,
+    redis_pool: &RedisPool,
+    redis_consumer_health: &RedisConsumerHealth,
+    group: &str,
+    mut submissions_tx: Sender<StreamSubmission>,
+) -> Result<()> {
+    for stream in ENV_CONFIG.stream_names.iter() {
+        group::ensure_group_exists(redis_pool, stream, group).await?;
+    }
+    let consumer = env::replica_name();
+
+    let mut interval = interval(RECLAIM_INTERVAL);
+    loop {
+        interval.tick().await;
+        debug!("checking for idle pending submissions");
+        for stream in ENV_CONFIG.stream_names.iter() {
+            reclaim_idle_submissions(
+                block_counter,
+                redis_pool,
+                redis_consumer_health,
+                stream,
+                group,
+                &consumer,
+                &mut submissions_tx,
+            )
+            .await?;
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! Periodically asks Redis how long each stream is, which ID it generated last and which is the
+//! oldest ID we haven't processed. Together with the last ID we processed this tells us how far
+//! behind we are.
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::{Context, Result};
+use fred::{pool::RedisPool, prelude::StreamsInterface, types::RedisValue};
+use tokio::time::interval;
+use tracing::{debug, warn};
+
+use crate::{
+    checkpoint::parse_stream_id,
+    env::ENV_CONFIG,
+    health::RedisConsumerHealth,
+    metrics::{self, CONSUMER_LAG, STREAM_LENGTH},
+    slot_clock::SLOT_CLOCK,
+};
+
+// How often we query the stream info.
+const STREAM_INFO_INTERVAL: Duration = Duration::from_secs(4);
+
+async fn update_stream_info(
+    redis_pool: &RedisPool,
+    redis_consumer_health: &RedisConsumerHealth,
+    stream: &str,
+) -> Result<()> {
+    let mut info: HashMap<String, RedisValue> = redis_pool
+        .xinfo_stream(stream, false, None)
+        .await
+        .map_err(metrics::redis_error("xinfo"))
+        .with_context(|| format!("failed to get stream info for {stream}"))?;
+
+    let length = info
+        .get("length")
+        .and_then(RedisValue::as_u64)
+        .context("expect stream info to contain length")?;
+    let last_generated_id = info
+        .remove("last-generated-id")
+        .and_then(RedisValue::into_string)
+        .context("expect stream info to contain last-generated-id")?;
+
+    // Lag counts from the oldest entry we haven't processed, the last one we did process may be
+    // from before the stream went quiet.
+    let last_processed_id = redis_consumer_health.last_processed_id(stream);
+    let oldest_unprocessed_id = match last_processed_id {
+        Some(last_processed_id)
+            if parse_stream_id(&last_generated_id) > parse_stream_id(&last_processed_id) =>
+        {
+            let entries: Vec<(String, HashMap<String, RedisValue>)> = redis_pool
+                .xrange_values(stream, format!("({last_processed_id}"), "+", Some(1))
+                .await
+                .map_err(metrics::redis_error("xrange"))
+                .with_context(|| format!("failed to read oldest unprocessed entry of {stream}"))?;
+            entries.into_iter().next().map(|(id, _fields)| id)
+        }
+        _ => None,
+    };
+
+    debug!(
+        stream,
+        length,
+        last_generated_id,
+        ?oldest_unprocessed_id,
+        "got stream info"
+    );
+    STREAM_LENGTH
+        .with_label_values(&[stream])
+        .set(length as i64);
+    redis_consumer_health.set_stream_info(stream, length, last_generated_id, oldest_unprocessed_id);
+
+    Ok(())
+}
+
+pub async fn update_stream_info_loop(
+    redis_pool: &RedisPool,
+    redis_consumer_health: &RedisConsumerHealth,
+) {
+    let mut interval = interval(STREAM_INFO_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        for stream in ENV_CONFIG.stream_names.iter() {
+            // A stream may not exist yet, and lag is only informational, neither should stop us
+            // from storing submissions.
+            if let Err(e) = update_stream_info(redis_pool, redis_consumer_health, stream).await {
+                warn!(stream, ?e, "failed to update stream info");
+            }
+        }
+
+        let now_millis = SLOT_CLOCK.now().as_millis() as u64;
+        for (stream, status) in redis_consumer_health.stream_statuses() {
+            if let Some(lag_ms) = status.lag_ms(now_millis) {
+                CONSUMER_LAG
+                    .with_label_values(&[&stream])
+                    .set(lag_ms as f64 / 1000.0);
+            }
+        }
+    }
+}
@@ -1,12 +1,19 @@
 //! Fns to read variables from the environment more conveniently and help other functions figure
 //! out what environment they're running in.
 
-use std::{env, fmt::Display};
+use std::env;
 
 use lazy_static::lazy_static;
 use tracing::{debug, warn};
 
-const SECRET_LOG_BLACKLIST: [&str; 1] = ["S3_SECRET_ACCESS_KEY"];
+use crate::{
+    consumer::backfill,
+    health::{ReadinessComponent, DEFAULT_READINESS_GATES},
+    network::{self, Network},
+    STREAM_NAME,
+};
+
+const SECRET_LOG_BLACKLIST: [&str; 2] = ["S3_SECRET_ACCESS_KEY", "S3_SESSION_TOKEN"];
 
 lazy_static! {
     pub static ref ENV_CONFIG: EnvConfig = get_env_config();
@@ -45,6 +52,24 @@ pub fn get_env_var_unsafe(key: &str) -> String {
     get_env_var(key).unwrap_or_else(|| panic!("{key} should be in env"))
 }
 
+// Used when no POD_NAME is available, e.g. when running locally.
+const FALLBACK_REPLICA_NAME: &str = "block-submission-service";
+
+/// The name of this replica, its consumer name in the consumer group and the writer in archive
+/// object names.
+pub fn replica_name() -> String {
+    match ENV_CONFIG.pod_name.as_ref() {
+        Some(pod_name) => pod_name.clone(),
+        None => {
+            warn!(
+                "no POD_NAME in env, using {} as replica name, replicas will share it",
+                FALLBACK_REPLICA_NAME
+            );
+            FALLBACK_REPLICA_NAME.to_string()
+        }
+    }
+}
+
 /// Some things do not need to be configurable explicitly but do differ between environments. This
 /// enum helps create those distinctions.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -76,58 +101,179 @@ fn get_env() -> Env {
 }
 
 fn get_env_bool(key: &str) -> bool {
-    get_env_var(key).map_or(false, |var| var.to_lowercase() == "true")
+    get_env_var(key).is_some_and(|var| var.to_lowercase() == "true")
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum Network {
-    Mainnet,
-    Goerli,
+fn get_env_u64(key: &str) -> Option<u64> {
+    get_env_var(key).map(|var| {
+        var.parse::<u64>()
+            .unwrap_or_else(|_| panic!("{key} present: {var}, but not a valid u64, panicking!"))
+    })
 }
 
-impl Display for Network {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Network::Mainnet => write!(f, "mainnet"),
-            Network::Goerli => write!(f, "goerli"),
-        }
-    }
+// A comma separated list, empty items are ignored.
+fn get_env_list(key: &str) -> Option<Vec<String>> {
+    get_env_var(key).map(|var| {
+        var.split(',')
+            .map(|item| item.trim().to_string())
+            .filter(|item| !item.is_empty())
+            .collect()
+    })
 }
 
 fn get_network() -> Network {
-    let network_str = get_env_var("NETWORK");
-    match network_str {
-        None => {
-            warn!("no NETWORK in env, assuming Mainnet");
-            Network::Mainnet
-        }
-        Some(str) => match str.to_lowercase().as_ref() {
-            "mainnet" => Network::Mainnet,
-            "goerli" => Network::Goerli,
-            _ => panic!("NETWORK present: {str}, but not one of [mainnet, goerli], panicking!"),
-        },
+    let networks = network::registry(get_env_var("NETWORK_CONFIG_PATH").as_deref())
+        .unwrap_or_else(|e| panic!("failed to load networks, panicking! {e:#}"));
+    let network_str = get_env_var("NETWORK").unwrap_or_else(|| {
+        warn!("no NETWORK in env, assuming mainnet");
+        "mainnet".to_string()
+    });
+    let name = network_str.to_lowercase();
+    networks
+        .iter()
+        .find(|network| network.name == name)
+        .cloned()
+        .unwrap_or_else(|| {
+            let known: Vec<&str> = networks
+                .iter()
+                .map(|network| network.name.as_str())
+                .collect();
+            panic!("NETWORK present: {network_str}, but not one of {known:?}, panicking!")
+        })
+}
+
+// Both bounds of the backfill range, checked up front, a bad range should stop us before we start.
+fn get_backfill_range() -> (Option<String>, Option<String>) {
+    let start = get_env_var("BACKFILL_START");
+    let end = get_env_var("BACKFILL_END");
+    if let Some(start) = start.as_deref() {
+        backfill::check_range(start, end.as_deref())
+            .unwrap_or_else(|e| panic!("BACKFILL_START present: {e:#}, panicking!"));
+    }
+    (start, end)
+}
+
+// Stale submissions are only passed on to be archived, without archiving storage would drop them.
+fn get_archive_flags() -> (bool, bool) {
+    let archive_stale_submissions = get_env_bool("ARCHIVE_STALE_SUBMISSIONS");
+    let archive_submissions = get_env_bool("ARCHIVE_SUBMISSIONS");
+    if archive_stale_submissions && !archive_submissions {
+        panic!("ARCHIVE_STALE_SUBMISSIONS present, but ARCHIVE_SUBMISSIONS is not, panicking!");
+    }
+    (archive_stale_submissions, archive_submissions)
+}
+
+fn get_readiness_gates() -> Vec<ReadinessComponent> {
+    match get_env_list("READINESS_GATES") {
+        None => DEFAULT_READINESS_GATES.to_vec(),
+        Some(names) => names
+            .iter()
+            .map(|name| {
+                name.parse()
+                    .unwrap_or_else(|e| panic!("READINESS_GATES present: {e}, panicking!"))
+            })
+            .collect(),
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct EnvConfig {
+    /// Pass stale submissions on to be archived, only skipping the Redis write for the relay.
+    /// Requires ARCHIVE_SUBMISSIONS.
+    pub archive_stale_submissions: bool,
+    /// Archive the submissions we process to S3, or a local directory when USE_LOCAL_STORE is set.
+    pub archive_submissions: bool,
+    /// Last stream ID or millisecond timestamp to backfill, defaults to the end of the stream.
+    pub backfill_end: Option<String>,
+    /// When set, replay the streams starting at this stream ID or millisecond timestamp.
+    pub backfill_start: Option<String>,
+    /// Key under which the ID of the last stored stream entry is persisted.
+    pub checkpoint_key: String,
+    /// Checkpoints older than this are ignored on startup, we start from new entries instead.
+    pub checkpoint_max_age_ms: u64,
+    /// When set, read the stream through this consumer group instead of a plain XREAD.
+    pub consumer_group: Option<String>,
+    /// When set, /livez reports unhealthy while we lag further behind a stream than this.
+    pub consumer_lag_threshold_ms: Option<u64>,
+    /// Stream to which entries we fail to decode are copied.
+    pub dead_letter_stream: String,
     pub env: Env,
+    /// How many slots after the end of their slot stored payloads expire.
+    pub expiry_margin_slots: u64,
+    /// Path to a JSON filter policy, see filter.rs.
+    pub filter_policy_path: Option<String>,
+    /// Submissions taking longer than this from being received to being stored are logged.
+    pub latency_slo_ms: Option<u64>,
+    /// Directory archive objects are written to when USE_LOCAL_STORE is set.
+    pub local_store_path: String,
     pub log_perf: bool,
     pub network: Network,
     pub pod_name: Option<String>,
+    /// Components which have to pass for /readyz to report ready, all are reported either way.
+    pub readiness_gates: Vec<ReadinessComponent>,
+    /// Lagging further behind a stream than this fails the consumer_lag readiness check. Separate
+    /// from CONSUMER_LAG_THRESHOLD_MS, which fails liveness.
+    pub readiness_max_lag_ms: u64,
+    /// Not having stored anything for longer than this fails the last_stored readiness check.
+    pub readiness_max_store_silence_ms: u64,
+    /// How long an entry has to sit unacknowledged in the consumer group before another consumer
+    /// may claim it.
+    pub reclaim_min_idle_ms: u64,
     pub redis_uri: String,
+    /// When unset, credentials are picked up the way AWS SDKs do, e.g. from an IAM role.
+    pub s3_access_key_id: Option<String>,
     pub s3_bucket: String,
+    /// An S3-compatible endpoint like MinIO, defaults to AWS.
+    pub s3_endpoint: Option<String>,
+    pub s3_region: Option<String>,
+    pub s3_secret_access_key: Option<String>,
+    pub s3_session_token: Option<String>,
+    /// Submissions for slots more than this many slots behind the current slot are stale and not
+    /// stored. When unset we store every submission.
+    pub stale_submission_slots: Option<u64>,
+    /// The streams to read block submissions from.
+    pub stream_names: Vec<String>,
+    /// Archive to LOCAL_STORE_PATH instead of S3.
     pub use_local_store: bool,
 }
 
 fn get_env_config() -> EnvConfig {
+    let (archive_stale_submissions, archive_submissions) = get_archive_flags();
+    let (backfill_start, backfill_end) = get_backfill_range();
     EnvConfig {
+        archive_stale_submissions,
+        archive_submissions,
+        backfill_end,
+        backfill_start,
+        checkpoint_key: get_env_var("CHECKPOINT_KEY")
+            .unwrap_or("block-submission-service:checkpoint".to_string()),
+        checkpoint_max_age_ms: get_env_u64("CHECKPOINT_MAX_AGE_MS").unwrap_or(48_000),
+        consumer_group: get_env_var("CONSUMER_GROUP"),
+        consumer_lag_threshold_ms: get_env_u64("CONSUMER_LAG_THRESHOLD_MS"),
+        dead_letter_stream: get_env_var("DEAD_LETTER_STREAM")
+            .unwrap_or("block-submission-archive:dlq".to_string()),
         env: get_env(),
+        expiry_margin_slots: get_env_u64("EXPIRY_MARGIN_SLOTS").unwrap_or(2),
+        filter_policy_path: get_env_var("FILTER_POLICY_PATH"),
+        latency_slo_ms: get_env_u64("LATENCY_SLO_MS"),
+        local_store_path: get_env_var("LOCAL_STORE_PATH").unwrap_or("archive".to_string()),
         log_perf: get_env_bool("LOG_PERF"),
         network: get_network(),
         pod_name: get_env_var("POD_NAME"),
+        readiness_gates: get_readiness_gates(),
+        readiness_max_lag_ms: get_env_u64("READINESS_MAX_LAG_MS").unwrap_or(12_000),
+        readiness_max_store_silence_ms: get_env_u64("READINESS_MAX_STORE_SILENCE_MS")
+            .unwrap_or(60_000),
+        reclaim_min_idle_ms: get_env_u64("RECLAIM_MIN_IDLE_MS").unwrap_or(12_000),
         redis_uri: get_env_var_unsafe("REDIS_URI"),
+        s3_access_key_id: get_env_var("S3_ACCESS_KEY_ID"),
         s3_bucket: get_env_var("S3_BUCKET").unwrap_or("block-submission-archive-dev".to_string()),
+        s3_endpoint: get_env_var("S3_ENDPOINT"),
+        s3_region: get_env_var("S3_REGION"),
+        s3_secret_access_key: get_env_var("S3_SECRET_ACCESS_KEY"),
+        s3_session_token: get_env_var("S3_SESSION_TOKEN"),
+        stale_submission_slots: get_env_u64("STALE_SUBMISSION_SLOTS"),
+        stream_names: get_env_list("STREAM_NAMES").unwrap_or(vec![STREAM_NAME.to_string()]),
         use_local_store: get_env_bool("USE_LOCAL_STORE"),
     }
 }
@@ -194,6 +340,33 @@ mod tests {
         assert!(!get_env_bool(test_key));
     }
 
+    #[test]
+    fn test_get_env_u64() {
+        let test_key = "TEST_KEY_U64";
+        std::env::set_var(test_key, "12000");
+        assert_eq!(get_env_u64(test_key), Some(12_000));
+        assert_eq!(get_env_u64("DOESNT_EXIST"), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_get_env_u64_panics() {
+        let test_key = "TEST_KEY_U64_INVALID";
+        std::env::set_var(test_key, "twelve");
+        get_env_u64(test_key);
+    }
+
+    #[test]
+    fn test_get_env_list() {
+        let test_key = "TEST_KEY_LIST";
+        std::env::set_var(test_key, "stream-a, stream-b,,");
+        assert_eq!(
+            get_env_list(test_key),
+            Some(vec!["stream-a".to_string(), "stream-b".to_string()])
+        );
+        assert_eq!(get_env_list("DOESNT_EXIST"), None);
+    }
+
     #[test]
     fn test_get_env() {
         std::env::set_var("ENV", "dev");
@@ -245,19 +418,19 @@ mod tests {
     #[test]
     fn test_get_network() {
         std::env::set_var("NETWORK", "mainnet");
-        assert_eq!(get_network(), Network::Mainnet);
+        assert_eq!(get_network().name, "mainnet");
 
-        std::env::set_var("NETWORK", "goerli");
-        assert_eq!(get_network(), Network::Goerli);
+        std::env::set_var("NETWORK", "holesky");
+        assert_eq!(get_network().name, "holesky");
 
-        std::env::set_var("NETWORK", "Mainnet");
-        assert_eq!(get_network(), Network::Mainnet);
+        std::env::set_var("NETWORK", "Sepolia");
+        assert_eq!(get_network().name, "sepolia");
 
         std::env::set_var("NETWORK", "Goerli");
-        assert_eq!(get_network(), Network::Goerli);
+        assert_eq!(get_network().name, "goerli");
 
         std::env::remove_var("NETWORK");
-        assert_eq!(get_network(), Network::Mainnet);
+        assert_eq!(get_network().name, "mainnet");
     }
 
     #[test]
@@ -0,0 +1,27 @@
+//! # Events
+//!
+//! Storage broadcasts an event for every payload it writes. Subscribers each read from the same
+//! bounded ring buffer, a subscriber falling behind misses events rather than slowing down storage.
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::bid_index::Bid;
+
+/// How many events a subscriber may fall behind before it starts missing events.
+pub const STORED_EVENTS_BUFFER_SIZE: usize = 1024;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct StoredEvent {
+    #[serde(flatten)]
+    pub bid: Bid,
+    /// Milliseconds since the unix epoch.
+    pub eligible_at: Option<u64>,
+    /// Milliseconds since the unix epoch.
+    pub stored_at: u64,
+}
+
+pub type StoredEventsTx = broadcast::Sender<StoredEvent>;
+
+pub fn stored_events_channel() -> StoredEventsTx {
+    broadcast::channel(STORED_EVENTS_BUFFER_SIZE).0
+}
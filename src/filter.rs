@@ -0,0 +1,317 @@
+//! # Filter
+//!
+//! Decides which block submissions we store. The policy is a predicate, loaded as JSON from the
+//! file at FILTER_POLICY_PATH. Without one, we store what the relay marked safe to propose.
+//!
+//! Predicates are written as single key objects, and combine with `all_of`, `any_of` and `not`.
+//! Predicates on optional fields reject submissions where the field is missing. For example:
+//!
+//! ```json
+//! {
+//!   "all_of": [
+//!     { "safe_to_propose": true },
+//!     { "status_code_in": [200] },
+//!     { "has_sim_validation_error": false },
+//!     { "min_value_wei": "1000000000000000" },
+//!     { "not": { "builder_pubkey_in": ["0xabc..."] } }
+//!   ]
+//! }
+//! ```
+use std::fmt::Display;
+
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Deserializer};
+use tracing::info;
+
+use crate::{env::ENV_CONFIG, BlockSubmission};
+
+lazy_static! {
+    pub static ref FILTER_POLICY: Predicate = get_filter_policy();
+}
+
+// Wei values overflow JSON numbers quickly, we accept them as decimal strings too.
+fn deserialize_wei<'de, D>(deserializer: D) -> Result<u128, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Wei {
+        Number(u64),
+        String(String),
+    }
+
+    match Wei::deserialize(deserializer)? {
+        Wei::Number(number) => Ok(number.into()),
+        Wei::String(string) => string.parse::<u128>().map_err(serde::de::Error::custom),
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Predicate {
+    AllOf(Vec<Predicate>),
+    AnyOf(Vec<Predicate>),
+    BuilderPubkeyIn(Vec<String>),
+    HasSimRequestError(bool),
+    HasSimValidationError(bool),
+    #[serde(deserialize_with = "deserialize_wei")]
+    MinValueWei(u128),
+    Not(Box<Predicate>),
+    ProposerPubkeyIn(Vec<String>),
+    SafeToPropose(bool),
+    SimOptimistic(bool),
+    SimWasSimulated(bool),
+    StatusCodeIn(Vec<u16>),
+}
+
+/// The predicate which rejected a submission.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Rejection(String);
+
+impl Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+fn contains_pubkey(pubkeys: &[String], pubkey: &str) -> bool {
+    pubkeys
+        .iter()
+        .any(|candidate| candidate.eq_ignore_ascii_case(pubkey))
+}
+
+impl Predicate {
+    fn name(&self) -> String {
+        match self {
+            Predicate::AllOf(_) => "all_of".to_string(),
+            Predicate::AnyOf(_) => "any_of".to_string(),
+            Predicate::BuilderPubkeyIn(_) => "builder_pubkey_in".to_string(),
+            Predicate::HasSimRequestError(_) => "has_sim_request_error".to_string(),
+            Predicate::HasSimValidationError(_) => "has_sim_validation_error".to_string(),
+            Predicate::MinValueWei(_) => "min_value_wei".to_string(),
+            Predicate::Not(predicate) => format!("not({})", predicate.name()),
+            Predicate::ProposerPubkeyIn(_) => "proposer_pubkey_in".to_string(),
+            Predicate::SafeToPropose(_) => "safe_to_propose".to_string(),
+            Predicate::SimOptimistic(_) => "sim_optimistic".to_string(),
+            Predicate::SimWasSimulated(_) => "sim_was_simulated".to_string(),
+            Predicate::StatusCodeIn(_) => "status_code_in".to_string(),
+        }
+    }
+
+    fn matches(&self, submission: &BlockSubmission) -> bool {
+        self.evaluate(submission).is_ok()
+    }
+
+    /// Accepts the submission, or returns the predicate which rejected it.
+    pub fn evaluate(&self, submission: &BlockSubmission) -> Result<(), Rejection> {
+        let accepted = match self {
+            Predicate::AllOf(predicates) => {
+                return predicates
+                    .iter()
+                    .try_for_each(|predicate| predicate.evaluate(submission));
+            }
+            Predicate::AnyOf(predicates) => predicates
+                .iter()
+                .any(|predicate| predicate.matches(submission)),
+            Predicate::BuilderPubkeyIn(pubkeys) => {
+                contains_pubkey(pubkeys, &submission.builder_pubkey())
+            }
+            Predicate::HasSimRequestError(expected) => {
+                submission.sim_request_error().is_some() == *expected
+            }
+            Predicate::HasSimValidationError(expected) => {
+                submission.sim_validation_error().is_some() == *expected
+            }
+            Predicate::MinValueWei(min_value) => submission.value() >= *min_value,
+            Predicate::Not(predicate) => !predicate.matches(submission),
+            Predicate::ProposerPubkeyIn(pubkeys) => {
+                contains_pubkey(pubkeys, &submission.proposer_pubkey())
+            }
+            Predicate::SafeToPropose(expected) => submission.safe_to_propose() == *expected,
+            Predicate::SimOptimistic(expected) => submission.sim_optimistic() == Some(*expected),
+            Predicate::SimWasSimulated(expected) => {
+                submission.sim_was_simulated() == Some(*expected)
+            }
+            Predicate::StatusCodeIn(status_codes) => submission
+                .status_code()
+                .is_some_and(|status_code| status_codes.contains(&status_code)),
+        };
+
+        if accepted {
+            Ok(())
+        } else {
+            Err(Rejection(self.name()))
+        }
+    }
+}
+
+fn read_filter_policy(path: &str) -> Result<Predicate> {
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("failed to open filter policy {path}"))?;
+    serde_json::from_reader(file).with_context(|| format!("failed to parse filter policy {path}"))
+}
+
+fn get_filter_policy() -> Predicate {
+    match ENV_CONFIG.filter_policy_path.as_ref() {
+        None => {
+            info!("no FILTER_POLICY_PATH in env, storing submissions safe to propose");
+            Predicate::SafeToPropose(true)
+        }
+        Some(path) => {
+            let policy = read_filter_policy(path)
+                .unwrap_or_else(|e| panic!("failed to load filter policy, panicking! {e:#}"));
+            info!(?policy, "loaded filter policy");
+            policy
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const BUILDER_PUBKEY: &str = "0x9192ca93e6c9b499d730a153e3dfba05bc13a91d50750265793de1f2488a66a7f3232f7b1ef374150365b9ea2cf9824d";
+    const PROPOSER_PUBKEY: &str = "0x8567a285bb5eee7373fb0f71df1f5a3367f6fa3fdf4a4931f51cd8bf56c00d6ce77836051ea1f797a21f9bd7b12275fd";
+
+    fn submission() -> BlockSubmission {
+        serde_json::from_value(json!({
+            "eligible_at": null,
+            "payload": {
+                "message": {
+                    "slot": "7323900",
+                    "parent_hash": "0xa82c987560729d49754f66858d7f25f460908d5477087cd701aaf5920ad305cd",
+                    "block_hash": "0xe2b6337a01144887af67f9b2cfb77df39d62033fedcfd3cb70d45035ff79ae3b",
+                    "builder_pubkey": BUILDER_PUBKEY,
+                    "proposer_pubkey": PROPOSER_PUBKEY,
+                    "proposer_fee_recipient": "0x388C818CA8B9251b393131C08a736A67ccB19297",
+                    "gas_limit": "29999972",
+                    "gas_used": "6635538",
+                    "value": "13620198421388901"
+                }
+            },
+            "received_at": 0,
+            "safe_to_propose": true,
+            "sim_optimistic": false,
+            "sim_request_error": null,
+            "sim_validation_error": "invalid block",
+            "sim_was_simulated": true,
+            "status_code": 400
+        }))
+        .unwrap()
+    }
+
+    fn policy(value: serde_json::Value) -> Predicate {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn parse_policy() {
+        let predicate = policy(json!({
+            "all_of": [
+                { "safe_to_propose": true },
+                { "min_value_wei": "340282366920938463463374607431768211455" },
+                { "not": { "status_code_in": [400] } }
+            ]
+        }));
+
+        assert_eq!(
+            predicate,
+            Predicate::AllOf(vec![
+                Predicate::SafeToPropose(true),
+                Predicate::MinValueWei(u128::MAX),
+                Predicate::Not(Box::new(Predicate::StatusCodeIn(vec![400]))),
+            ])
+        );
+    }
+
+    #[test]
+    fn min_value_wei_accepts_numbers() {
+        assert_eq!(
+            policy(json!({ "min_value_wei": 1000 })),
+            Predicate::MinValueWei(1000)
+        );
+    }
+
+    #[test]
+    fn evaluate_field_predicates() {
+        let submission = submission();
+
+        let accepted = [
+            json!({ "safe_to_propose": true }),
+            json!({ "status_code_in": [200, 400] }),
+            json!({ "has_sim_validation_error": true }),
+            json!({ "has_sim_request_error": false }),
+            json!({ "sim_was_simulated": true }),
+            json!({ "sim_optimistic": false }),
+            json!({ "builder_pubkey_in": [BUILDER_PUBKEY.to_uppercase().replace("0X", "0x")] }),
+            json!({ "proposer_pubkey_in": [PROPOSER_PUBKEY] }),
+            json!({ "min_value_wei": "13620198421388901" }),
+        ];
+        for predicate in accepted {
+            assert_eq!(
+                policy(predicate.clone()).evaluate(&submission),
+                Ok(()),
+                "{predicate}"
+            );
+        }
+
+        let rejected = [
+            json!({ "status_code_in": [200] }),
+            json!({ "has_sim_validation_error": false }),
+            json!({ "sim_optimistic": true }),
+            json!({ "min_value_wei": "13620198421388902" }),
+        ];
+        for predicate in rejected {
+            assert!(
+                policy(predicate.clone()).evaluate(&submission).is_err(),
+                "{predicate}"
+            );
+        }
+    }
+
+    #[test]
+    fn missing_optional_field_rejects() {
+        let submission = BlockSubmission::default();
+        assert!(policy(json!({ "status_code_in": [200] }))
+            .evaluate(&submission)
+            .is_err());
+        assert!(policy(json!({ "sim_was_simulated": false }))
+            .evaluate(&submission)
+            .is_err());
+    }
+
+    #[test]
+    fn rejection_names_the_predicate() {
+        let submission = submission();
+
+        let all_of = policy(json!({
+            "all_of": [
+                { "safe_to_propose": true },
+                { "has_sim_validation_error": false },
+                { "status_code_in": [200] }
+            ]
+        }));
+        assert_eq!(
+            all_of.evaluate(&submission),
+            Err(Rejection("has_sim_validation_error".to_string()))
+        );
+
+        let not = policy(json!({ "not": { "builder_pubkey_in": [BUILDER_PUBKEY] } }));
+        assert_eq!(
+            not.evaluate(&submission),
+            Err(Rejection("not(builder_pubkey_in)".to_string()))
+        );
+
+        let any_of = policy(json!({
+            "any_of": [{ "status_code_in": [200] }, { "sim_optimistic": true }]
+        }));
+        assert_eq!(
+            any_of.evaluate(&submission),
+            Err(Rejection("any_of".to_string()))
+        );
+    }
+}
@@ -0,0 +1,81 @@
+//! # Fork
+//!
+//! The consensus fork a block submission was built for. It decides the shape of the payload and
+//! the key layout the relay reads stored payloads from. Which fork a submission belongs to follows
+//! from its slot and the network's fork schedule, so we keep working across a fork boundary
+//! without a redeploy.
+use std::fmt::Display;
+
+use serde::Deserialize;
+
+const SLOTS_PER_EPOCH: u64 = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Fork {
+    Capella,
+    Deneb,
+    Electra,
+}
+
+impl Display for Fork {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fork::Capella => write!(f, "capella"),
+            Fork::Deneb => write!(f, "deneb"),
+            Fork::Electra => write!(f, "electra"),
+        }
+    }
+}
+
+/// The epochs at which forks activate, forks which are not scheduled yet are None. Each network
+/// has its own, see network.rs.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct ForkSchedule {
+    pub capella_epoch: u64,
+    pub deneb_epoch: Option<u64>,
+    pub electra_epoch: Option<u64>,
+}
+
+impl ForkSchedule {
+    /// We only ever handled submissions from Capella onwards, earlier slots are taken to be
+    /// Capella too.
+    pub fn fork_at_slot(&self, slot: u64) -> Fork {
+        let epoch = slot / SLOTS_PER_EPOCH;
+        let activated = |fork_epoch: Option<u64>| fork_epoch.is_some_and(|fork| epoch >= fork);
+        if activated(self.electra_epoch) {
+            Fork::Electra
+        } else if activated(self.deneb_epoch) {
+            Fork::Deneb
+        } else {
+            Fork::Capella
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fork_at_slot_test() {
+        let schedule = ForkSchedule {
+            capella_epoch: 194048,
+            deneb_epoch: Some(269568),
+            electra_epoch: Some(364032),
+        };
+        assert_eq!(schedule.fork_at_slot(7323900), Fork::Capella);
+        assert_eq!(schedule.fork_at_slot(269568 * 32 - 1), Fork::Capella);
+        assert_eq!(schedule.fork_at_slot(269568 * 32), Fork::Deneb);
+        assert_eq!(schedule.fork_at_slot(364032 * 32), Fork::Electra);
+    }
+
+    #[test]
+    fn unscheduled_forks_never_activate() {
+        let schedule = ForkSchedule {
+            capella_epoch: 0,
+            deneb_epoch: None,
+            electra_epoch: None,
+        };
+        assert_eq!(schedule.fork_at_slot(u64::MAX), Fork::Capella);
+    }
+}
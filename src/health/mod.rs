@@ -1,14 +1,18 @@
+mod readiness;
 mod redis;
 mod redis_consumer;
+mod task;
 
+pub use readiness::{get_readyz, ReadinessComponent, DEFAULT_READINESS_GATES};
 pub use redis::RedisHealth;
 pub use redis_consumer::RedisConsumerHealth;
+pub use task::TaskHealth;
 
 use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
 use serde_json::json;
 use tracing::{debug, warn};
 
-use crate::server::AppState;
+use crate::{env::ENV_CONFIG, server::AppState, slot_clock::SLOT_CLOCK};
 
 trait HealthCheck {
     fn health_status(&self) -> (bool, String);
@@ -21,19 +25,48 @@ pub async fn get_livez(State(state): State<AppState>) -> impl IntoResponse {
     // check.
     let (_is_messages_healthy, messages_health_status) =
         state.redis_consumer_health.health_status();
+    let now_millis = SLOT_CLOCK.now().as_millis() as u64;
+    let (is_lag_healthy, lag_health_status) = state
+        .redis_consumer_health
+        .lag_status(now_millis, ENV_CONFIG.consumer_lag_threshold_ms);
 
-    let message = json!({ "redis": redis_health_status, "messages": messages_health_status });
+    let streams: serde_json::Map<String, serde_json::Value> = state
+        .redis_consumer_health
+        .stream_statuses()
+        .into_iter()
+        .map(|(stream, status)| {
+            let status = json!({
+                "last_processed_id": status.last_processed_id,
+                "last_generated_id": status.last_generated_id,
+                "length": status.length,
+                "lag_ms": status.lag_ms(now_millis),
+            });
+            (stream, status)
+        })
+        .collect();
 
-    if is_redis_healthy {
+    let message = json!({
+        "redis": redis_health_status,
+        "messages": messages_health_status,
+        "lag": lag_health_status,
+        "streams": streams,
+        "reclaimed": state.redis_consumer_health.reclaimed_count(),
+        "dead_lettered": state.redis_consumer_health.dead_lettered_count(),
+        "stale": state.redis_consumer_health.stale_count(),
+    });
+
+    if is_redis_healthy && is_lag_healthy {
         debug!(
             redis = redis_health_status,
-            messages = messages_health_status
+            messages = messages_health_status,
+            lag = lag_health_status
         );
         (StatusCode::OK, Json(message))
     } else {
         warn!(
             redis = redis_health_status,
-            messages = messages_health_status
+            messages = messages_health_status,
+            lag = lag_health_status
         );
         (StatusCode::SERVICE_UNAVAILABLE, Json(message))
     }
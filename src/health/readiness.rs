@@ -0,0 +1,273 @@
+//! Readiness, unlike liveness, may fail while the service recovers on its own. Each component is
+//! checked and reported separately, only those in READINESS_GATES decide whether we're ready.
+use std::{collections::BTreeMap, fmt::Display, str::FromStr, time::Duration};
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde::Serialize;
+use tracing::{debug, warn};
+
+use crate::{
+    env::ENV_CONFIG, metrics::CHANNEL_DEPTH, server::AppState, slot_clock::SLOT_CLOCK,
+    SUBMISSIONS_BUFFER_SIZE,
+};
+
+use super::HealthCheck;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ReadinessComponent {
+    ChannelSaturation,
+    ConsumerLag,
+    ConsumerTask,
+    LastStored,
+    Redis,
+    StoreTask,
+}
+
+const ALL_COMPONENTS: [ReadinessComponent; 6] = [
+    ReadinessComponent::ChannelSaturation,
+    ReadinessComponent::ConsumerLag,
+    ReadinessComponent::ConsumerTask,
+    ReadinessComponent::LastStored,
+    ReadinessComponent::Redis,
+    ReadinessComponent::StoreTask,
+];
+
+/// Lag and storage silence may be down to a quiet stream, by default they don't gate readiness.
+pub const DEFAULT_READINESS_GATES: [ReadinessComponent; 4] = [
+    ReadinessComponent::ChannelSaturation,
+    ReadinessComponent::ConsumerTask,
+    ReadinessComponent::Redis,
+    ReadinessComponent::StoreTask,
+];
+
+impl ReadinessComponent {
+    fn name(&self) -> &'static str {
+        match self {
+            ReadinessComponent::ChannelSaturation => "channel_saturation",
+            ReadinessComponent::ConsumerLag => "consumer_lag",
+            ReadinessComponent::ConsumerTask => "consumer_task",
+            ReadinessComponent::LastStored => "last_stored",
+            ReadinessComponent::Redis => "redis",
+            ReadinessComponent::StoreTask => "store_task",
+        }
+    }
+}
+
+impl Display for ReadinessComponent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for ReadinessComponent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ALL_COMPONENTS
+            .into_iter()
+            .find(|component| component.name() == s)
+            .ok_or_else(|| format!("unknown readiness component {s}"))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+struct ComponentStatus {
+    pass: bool,
+    gating: bool,
+    status: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Readiness {
+    ready: bool,
+    components: BTreeMap<&'static str, ComponentStatus>,
+}
+
+fn task_status(is_running: bool) -> (bool, String) {
+    if is_running {
+        (true, "healthy, running".to_string())
+    } else {
+        (false, "unhealthy, not running".to_string())
+    }
+}
+
+// A full channel means storage can't keep up and the consumer is blocked on it.
+fn channel_status(depth: i64, capacity: usize) -> (bool, String) {
+    let saturation = depth as f64 / capacity as f64;
+    if depth >= capacity as i64 {
+        (
+            false,
+            format!("unhealthy, channel full, {depth} of {capacity} submissions waiting"),
+        )
+    } else {
+        (
+            true,
+            format!("healthy, channel {:.0}% full", saturation * 100.0),
+        )
+    }
+}
+
+fn last_stored_status(
+    time_since_last_stored: Option<Duration>,
+    time_since_start: Duration,
+    max_silence: Duration,
+) -> (bool, String) {
+    match time_since_last_stored {
+        None if time_since_start > max_silence => (
+            false,
+            format!(
+                "unhealthy, started {} seconds ago, but nothing stored",
+                time_since_start.as_secs()
+            ),
+        ),
+        None => (
+            true,
+            format!(
+                "healthy, started {} seconds ago, waiting for first store",
+                time_since_start.as_secs()
+            ),
+        ),
+        Some(time_since_last_stored) if time_since_last_stored > max_silence => (
+            false,
+            format!(
+                "unhealthy, last stored {} seconds ago",
+                time_since_last_stored.as_secs()
+            ),
+        ),
+        Some(time_since_last_stored) => (
+            true,
+            format!(
+                "healthy, last stored {} seconds ago",
+                time_since_last_stored.as_secs()
+            ),
+        ),
+    }
+}
+
+fn check(component: ReadinessComponent, state: &AppState) -> (bool, String) {
+    match component {
+        ReadinessComponent::ChannelSaturation => {
+            channel_status(CHANNEL_DEPTH.get(), SUBMISSIONS_BUFFER_SIZE)
+        }
+        ReadinessComponent::ConsumerLag => state.redis_consumer_health.lag_status(
+            SLOT_CLOCK.now().as_millis() as u64,
+            Some(ENV_CONFIG.readiness_max_lag_ms),
+        ),
+        ReadinessComponent::ConsumerTask => task_status(state.consumer_task_health.is_running()),
+        ReadinessComponent::LastStored => last_stored_status(
+            state.block_counter.time_since_last_stored(),
+            state.block_counter.time_since_start(),
+            Duration::from_millis(ENV_CONFIG.readiness_max_store_silence_ms),
+        ),
+        ReadinessComponent::Redis => state.redis_health.health_status(),
+        ReadinessComponent::StoreTask => task_status(state.store_task_health.is_running()),
+    }
+}
+
+fn evaluate_readiness(
+    statuses: impl IntoIterator<Item = (ReadinessComponent, (bool, String))>,
+    gates: &[ReadinessComponent],
+) -> Readiness {
+    let components: BTreeMap<&'static str, ComponentStatus> = statuses
+        .into_iter()
+        .map(|(component, (pass, status))| {
+            let status = ComponentStatus {
+                pass,
+                gating: gates.contains(&component),
+                status,
+            };
+            (component.name(), status)
+        })
+        .collect();
+    let ready = components
+        .values()
+        .all(|status| status.pass || !status.gating);
+
+    Readiness { ready, components }
+}
+
+pub async fn get_readyz(State(state): State<AppState>) -> impl IntoResponse {
+    let statuses = ALL_COMPONENTS.map(|component| (component, check(component, &state)));
+    let readiness = evaluate_readiness(statuses, &ENV_CONFIG.readiness_gates);
+
+    if readiness.ready {
+        debug!(?readiness, "ready");
+        (StatusCode::OK, Json(readiness))
+    } else {
+        warn!(?readiness, "not ready");
+        (StatusCode::SERVICE_UNAVAILABLE, Json(readiness))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_component() {
+        for component in ALL_COMPONENTS {
+            assert_eq!(component.to_string().parse(), Ok(component));
+        }
+        assert!("redis_connection".parse::<ReadinessComponent>().is_err());
+    }
+
+    #[test]
+    fn channel_status_test() {
+        assert!(channel_status(0, 128).0);
+        assert!(channel_status(127, 128).0);
+        assert!(!channel_status(128, 128).0);
+    }
+
+    #[test]
+    fn last_stored_status_test() {
+        let max_silence = Duration::from_secs(60);
+        assert!(last_stored_status(None, Duration::from_secs(10), max_silence).0);
+        assert!(!last_stored_status(None, Duration::from_secs(61), max_silence).0);
+        assert!(
+            last_stored_status(
+                Some(Duration::from_secs(5)),
+                Duration::from_secs(600),
+                max_silence
+            )
+            .0
+        );
+        assert!(
+            !last_stored_status(
+                Some(Duration::from_secs(61)),
+                Duration::from_secs(600),
+                max_silence
+            )
+            .0
+        );
+    }
+
+    #[test]
+    fn only_gating_components_decide_readiness() {
+        let statuses = || {
+            [
+                (ReadinessComponent::Redis, (true, "healthy".to_string())),
+                (
+                    ReadinessComponent::ConsumerLag,
+                    (false, "unhealthy".to_string()),
+                ),
+            ]
+        };
+
+        let readiness = evaluate_readiness(statuses(), &[ReadinessComponent::Redis]);
+        assert!(readiness.ready);
+        assert_eq!(
+            readiness.components["consumer_lag"],
+            ComponentStatus {
+                pass: false,
+                gating: false,
+                status: "unhealthy".to_string()
+            }
+        );
+
+        let readiness = evaluate_readiness(
+            statuses(),
+            &[ReadinessComponent::Redis, ReadinessComponent::ConsumerLag],
+        );
+        assert!(!readiness.ready);
+    }
+}
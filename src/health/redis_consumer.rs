@@ -1,18 +1,62 @@
 use std::{
-    sync::{Arc, Mutex},
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
     time::{Duration, Instant},
 };
 
 use lazy_static::lazy_static;
+use serde::Serialize;
 
-use crate::env::{Env, ENV_CONFIG};
+use crate::{
+    checkpoint::{parse_stream_id, stream_id_millis},
+    env::{Env, ENV_CONFIG},
+};
 
 use super::HealthCheck;
 
+/// What we know about a stream we consume, from our own reads and from XINFO STREAM.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct StreamStatus {
+    pub last_generated_id: Option<String>,
+    pub last_processed_id: Option<String>,
+    pub length: Option<u64>,
+    /// The oldest entry we hadn't processed yet when we asked for the stream info.
+    pub oldest_unprocessed_id: Option<String>,
+}
+
+impl StreamStatus {
+    /// How far behind we are, going by the time in the oldest stream ID we haven't processed.
+    /// Going by the last processed ID instead would count a quiet stream's idle time as lag. Once
+    /// we've processed the last generated ID we're caught up, however long ago that was.
+    pub fn lag_ms(&self, now_millis: u64) -> Option<u64> {
+        let processed_id = parse_stream_id(self.last_processed_id.as_deref()?)?;
+        let caught_up = self
+            .last_generated_id
+            .as_deref()
+            .and_then(parse_stream_id)
+            .is_some_and(|generated_id| generated_id <= processed_id);
+        if caught_up {
+            return Some(0);
+        }
+
+        // We may have processed the oldest unprocessed entry since, the next one is no older than
+        // the last one we processed.
+        let unprocessed_millis = stream_id_millis(self.oldest_unprocessed_id.as_deref()?)?;
+        Some(now_millis.saturating_sub(unprocessed_millis.max(processed_id.0)))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RedisConsumerHealth {
+    dead_lettered_count: Arc<AtomicU64>,
     last_message_received: Arc<Mutex<Option<Instant>>>,
+    reclaimed_count: Arc<AtomicU64>,
+    stale_count: Arc<AtomicU64>,
     started_on: Instant,
+    streams: Arc<Mutex<HashMap<String, StreamStatus>>>,
 }
 
 impl Default for RedisConsumerHealth {
@@ -24,8 +68,12 @@ impl Default for RedisConsumerHealth {
 impl RedisConsumerHealth {
     pub fn new() -> Self {
         Self {
+            dead_lettered_count: Arc::new(AtomicU64::new(0)),
             last_message_received: Arc::new(Mutex::new(None)),
+            reclaimed_count: Arc::new(AtomicU64::new(0)),
+            stale_count: Arc::new(AtomicU64::new(0)),
             started_on: Instant::now(),
+            streams: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
@@ -39,6 +87,107 @@ impl RedisConsumerHealth {
     pub fn set_last_message_received_now(&self) {
         self.set_last_message_received(Instant::now());
     }
+
+    /// Count pending entries claimed from other consumers in the group.
+    pub fn add_reclaimed(&self, count: u64) {
+        self.reclaimed_count.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn reclaimed_count(&self) -> u64 {
+        self.reclaimed_count.load(Ordering::Relaxed)
+    }
+
+    /// Count entries we failed to decode and sent to the dead-letter stream.
+    pub fn increment_dead_lettered(&self) {
+        self.dead_lettered_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dead_lettered_count(&self) -> u64 {
+        self.dead_lettered_count.load(Ordering::Relaxed)
+    }
+
+    /// Count submissions for slots too far behind the current slot.
+    pub fn increment_stale(&self) {
+        self.stale_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stale_count(&self) -> u64 {
+        self.stale_count.load(Ordering::Relaxed)
+    }
+
+    fn update_stream(&self, stream: &str, update: impl FnOnce(&mut StreamStatus)) {
+        let mut streams = self
+            .streams
+            .lock()
+            .expect("expect to be able to acquire streams lock");
+        update(streams.entry(stream.to_string()).or_default());
+    }
+
+    /// The ID of the newest entry we're done with, stored or skipped. Entries are done out of
+    /// order, an older ID doesn't move it back.
+    pub fn set_last_processed_id(&self, stream: &str, id: &str) {
+        self.update_stream(stream, |status| {
+            let newer = status
+                .last_processed_id
+                .as_deref()
+                .and_then(parse_stream_id)
+                .is_none_or(|last_processed_id| {
+                    parse_stream_id(id).is_some_and(|id| id > last_processed_id)
+                });
+            if newer {
+                status.last_processed_id = Some(id.to_string());
+            }
+        });
+    }
+
+    pub fn last_processed_id(&self, stream: &str) -> Option<String> {
+        self.streams
+            .lock()
+            .expect("expect to be able to acquire streams lock")
+            .get(stream)
+            .and_then(|status| status.last_processed_id.clone())
+    }
+
+    /// The stream length and last generated ID, as reported by XINFO STREAM, together with the
+    /// oldest entry we hadn't processed at the time.
+    pub fn set_stream_info(
+        &self,
+        stream: &str,
+        length: u64,
+        last_generated_id: String,
+        oldest_unprocessed_id: Option<String>,
+    ) {
+        self.update_stream(stream, |status| {
+            status.length = Some(length);
+            status.last_generated_id = Some(last_generated_id);
+            status.oldest_unprocessed_id = oldest_unprocessed_id;
+        });
+    }
+
+    pub fn stream_statuses(&self) -> HashMap<String, StreamStatus> {
+        self.streams
+            .lock()
+            .expect("expect to be able to acquire streams lock")
+            .clone()
+    }
+
+    /// Whether every stream lags less than the threshold, when one is configured.
+    pub fn lag_status(&self, now_millis: u64, threshold_ms: Option<u64>) -> (bool, String) {
+        let max_lag_ms = self
+            .stream_statuses()
+            .values()
+            .filter_map(|status| status.lag_ms(now_millis))
+            .max();
+
+        match (max_lag_ms, threshold_ms) {
+            (None, _) => (true, "healthy, lag unknown".to_string()),
+            (Some(max_lag_ms), Some(threshold_ms)) if max_lag_ms > threshold_ms => (
+                false,
+                format!("unhealthy, lagging {max_lag_ms}ms behind, more than {threshold_ms}ms"),
+            ),
+            (Some(max_lag_ms), _) => (true, format!("healthy, lagging {max_lag_ms}ms behind")),
+        }
+    }
 }
 
 lazy_static! {
@@ -109,3 +258,78 @@ impl HealthCheck for RedisConsumerHealth {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(
+        last_processed_id: Option<&str>,
+        last_generated_id: Option<&str>,
+        oldest_unprocessed_id: Option<&str>,
+    ) -> StreamStatus {
+        StreamStatus {
+            last_generated_id: last_generated_id.map(str::to_string),
+            last_processed_id: last_processed_id.map(str::to_string),
+            length: None,
+            oldest_unprocessed_id: oldest_unprocessed_id.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn lag_ms_test() {
+        assert_eq!(status(None, Some("1000-0"), None).lag_ms(5000), None);
+        // Without stream info we don't know whether anything is waiting.
+        assert_eq!(status(Some("1000-0"), None, None).lag_ms(5000), None);
+        // The stream was quiet until 3000, that's not lag.
+        assert_eq!(
+            status(Some("1000-0"), Some("3000-0"), Some("3000-0")).lag_ms(5000),
+            Some(2000)
+        );
+        assert_eq!(
+            status(Some("1000-0"), Some("3000-0"), Some("1500-0")).lag_ms(5000),
+            Some(3500)
+        );
+        // We processed past the oldest unprocessed entry since we asked.
+        assert_eq!(
+            status(Some("2000-0"), Some("3000-0"), Some("1500-0")).lag_ms(5000),
+            Some(3000)
+        );
+        assert_eq!(
+            status(Some("1000-3"), Some("1000-3"), None).lag_ms(5000),
+            Some(0)
+        );
+        assert_eq!(
+            status(Some("1000-3"), Some("1000-4"), Some("1000-4")).lag_ms(5000),
+            Some(4000)
+        );
+        // An empty stream has never generated an ID.
+        assert_eq!(status(Some("0-0"), Some("0-0"), None).lag_ms(5000), Some(0));
+    }
+
+    #[test]
+    fn last_processed_id_only_moves_forward() {
+        let health = RedisConsumerHealth::new();
+        health.set_last_processed_id("a", "9-0");
+        health.set_last_processed_id("a", "10-0");
+        health.set_last_processed_id("a", "9-5");
+        assert_eq!(health.last_processed_id("a").as_deref(), Some("10-0"));
+    }
+
+    #[test]
+    fn lag_status_uses_threshold() {
+        let health = RedisConsumerHealth::new();
+        assert!(health.lag_status(5000, Some(1000)).0);
+
+        health.set_last_processed_id("a", "4500-0");
+        health.set_last_processed_id("b", "3000-0");
+        health.set_stream_info("a", 10, "4500-0".to_string(), None);
+        health.set_stream_info("b", 10, "4800-0".to_string(), Some("3500-0".to_string()));
+        assert!(health.lag_status(5000, None).0);
+        assert!(health.lag_status(5000, Some(2000)).0);
+        assert!(!health.lag_status(5000, Some(1000)).0);
+
+        health.set_last_processed_id("b", "4800-0");
+        assert!(health.lag_status(5000, Some(1000)).0);
+    }
+}
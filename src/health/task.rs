@@ -0,0 +1,52 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// Tracks whether a long running task is still running.
+#[derive(Debug, Clone, Default)]
+pub struct TaskHealth {
+    running: Arc<AtomicBool>,
+}
+
+/// Marks the task as stopped when dropped, also when the task panics.
+pub struct RunningGuard(Arc<AtomicBool>);
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
+impl TaskHealth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Marks the task as running until the returned guard is dropped.
+    pub fn start(&self) -> RunningGuard {
+        self.running.store(true, Ordering::Relaxed);
+        RunningGuard(self.running.clone())
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn running_until_guard_dropped() {
+        let task_health = TaskHealth::new();
+        assert!(!task_health.is_running());
+
+        let guard = task_health.start();
+        assert!(task_health.clone().is_running());
+
+        drop(guard);
+        assert!(!task_health.is_running());
+    }
+}
@@ -1,24 +1,45 @@
+mod api;
+pub mod archiver;
+mod best_bid;
+mod bid_index;
 mod block_submission_key;
 mod block_submissions;
+pub mod checkpoint;
 mod consumer;
 pub mod env;
+pub mod events;
+pub mod filter;
+pub mod fork;
 mod health;
 pub mod log;
+pub mod metrics;
+pub mod network;
+pub mod payload;
 pub mod performance;
 mod server;
+pub mod slot_clock;
 mod storage;
 
+pub use archiver::run_archive_submissions_thread;
 pub use block_submission_key::BlockSubmissionKey;
 pub use block_submissions::BlockSubmission;
+pub use consumer::run_backfill_submissions_thread;
 pub use consumer::run_consume_submissions_thread;
+pub use consumer::run_reclaim_submissions_thread;
+pub use consumer::run_stream_info_thread;
+pub use consumer::StreamSubmission;
 pub use health::RedisConsumerHealth;
 pub use health::RedisHealth;
+pub use health::TaskHealth;
 pub use server::run_server_thread;
 pub use server::AppState;
-pub use storage::run_store_submissions_thread;
+pub use storage::{run_store_submissions_thread, StoreState};
 
 pub type JsonValue = serde_json::value::Value;
 
 type Slot = i32;
 
 pub const STREAM_NAME: &str = "block-submission-archive";
+
+/// Capacity of the channel between the consumer and storage.
+pub const SUBMISSIONS_BUFFER_SIZE: usize = 128;
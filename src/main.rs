@@ -11,39 +11,69 @@ use std::sync::Arc;
 
 use anyhow::{Context, Result};
 use block_submission_service::{
+    archiver::{self, ArchiveStore},
+    checkpoint::{self, Checkpoint},
     env::ENV_CONFIG,
-    log,
-    performance::{self, BlockCounter},
-    run_consume_submissions_thread, run_server_thread, run_store_submissions_thread,
-    RedisConsumerHealth, RedisHealth,
+    events, filter, log,
+    performance::{self, BlockCounter, StoreLatencies},
+    run_archive_submissions_thread, run_backfill_submissions_thread,
+    run_consume_submissions_thread, run_reclaim_submissions_thread, run_server_thread,
+    run_store_submissions_thread, run_stream_info_thread,
+    slot_clock::SLOT_CLOCK,
+    AppState, RedisConsumerHealth, RedisHealth, StoreState, TaskHealth, SUBMISSIONS_BUFFER_SIZE,
 };
 use fred::{pool::RedisPool, types::RedisConfig};
 use futures::{channel::mpsc::channel, try_join};
-use tokio::sync::Notify;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::Notify,
+};
 use tracing::{info, trace};
 
-const SUBMISSIONS_BUFFER_SIZE: usize = 128;
-
 #[tokio::main]
 async fn main() -> Result<()> {
     log::init();
 
     info!("starting block submission service");
 
+    // Load the filter policy up front, a bad policy should stop us before we start consuming.
+    lazy_static::initialize(&filter::FILTER_POLICY);
+
+    info!(
+        network = %ENV_CONFIG.network,
+        current_slot = ?SLOT_CLOCK.current_slot(),
+        "using slot clock"
+    );
+
     // When one of our threads panics, we want to shutdown the entire program. Most threads
     // communicate over channels, and so will naturally shut down as the channels close. However,
     // the server thread does not. We use this notify to shutdown the server thread when any other
     // thread panics.
     let shutdown_notify = Arc::new(Notify::new());
 
-    // Track our block archival count.
+    // On deploys we receive a SIGTERM, we shut down the same way, giving threads a chance to clean
+    // up e.g. write a final checkpoint.
+    let mut sigterm = signal(SignalKind::terminate()).context("failed to listen for SIGTERM")?;
+    tokio::spawn({
+        let shutdown_notify = shutdown_notify.clone();
+        async move {
+            sigterm.recv().await;
+            info!("received SIGTERM, shutting down");
+            shutdown_notify.notify_waiters();
+        }
+    });
+
+    // Track our submission throughput and store latency.
     let block_counter = Arc::new(BlockCounter::new());
+    let store_latencies = Arc::new(StoreLatencies::new());
     let log_block_counter_thread = {
         if tracing::enabled!(tracing::Level::INFO) || ENV_CONFIG.log_perf {
             let handle = tokio::spawn({
                 let block_counter = block_counter.clone();
+                let store_latencies = store_latencies.clone();
                 async move {
-                    performance::report_storage_rate_periodically(&block_counter).await;
+                    performance::report_storage_rate_periodically(&block_counter, &store_latencies)
+                        .await;
                 }
             });
             let shutdown_notify = shutdown_notify.clone();
@@ -71,30 +101,101 @@ async fn main() -> Result<()> {
 
     let redis_health = RedisHealth::new(redis_pool.clone());
     let redis_consumer_health = RedisConsumerHealth::new();
+    let consumer_task_health = TaskHealth::new();
+    let store_task_health = TaskHealth::new();
+    let stored_events_tx = events::stored_events_channel();
 
     let (submissions_tx, submissions_rx) = channel(SUBMISSIONS_BUFFER_SIZE);
 
+    let checkpoint = Checkpoint::new();
+    let checkpoint_thread = checkpoint::run_checkpoint_thread(
+        checkpoint.clone(),
+        redis_pool.clone(),
+        shutdown_notify.clone(),
+    );
+
+    let backfill_submissions_thread = run_backfill_submissions_thread(
+        block_counter.clone(),
+        redis_consumer_health.clone(),
+        redis_pool.clone(),
+        shutdown_notify.clone(),
+        submissions_tx.clone(),
+    );
+
+    let reclaim_submissions_thread = run_reclaim_submissions_thread(
+        block_counter.clone(),
+        redis_consumer_health.clone(),
+        redis_pool.clone(),
+        shutdown_notify.clone(),
+        submissions_tx.clone(),
+    );
+
     let cache_submissions_thread = run_consume_submissions_thread(
+        block_counter.clone(),
+        checkpoint.clone(),
+        consumer_task_health.clone(),
         redis_consumer_health.clone(),
         redis_pool.clone(),
         shutdown_notify.clone(),
         submissions_tx,
     );
 
+    let stream_info_thread = run_stream_info_thread(
+        redis_consumer_health.clone(),
+        redis_pool.clone(),
+        shutdown_notify.clone(),
+    );
+
+    // Archiving is optional, without it storage has nobody to hand submissions to.
+    let (archive_tx, archive_submissions_thread) = if ENV_CONFIG.archive_submissions {
+        let archive_store = ArchiveStore::from_env().context("failed to set up archive store")?;
+        let (archive_tx, archive_rx) = archiver::archive_channel();
+        let handle =
+            run_archive_submissions_thread(archive_rx, archive_store, shutdown_notify.clone());
+        (Some(archive_tx), handle)
+    } else {
+        trace!("archiving disabled, not starting archive submissions thread");
+        (None, tokio::spawn(async {}))
+    };
+
     let store_submissions_thread = run_store_submissions_thread(
-        block_counter,
-        redis_pool,
         shutdown_notify.clone(),
+        StoreState {
+            archive_tx,
+            block_counter: block_counter.clone(),
+            checkpoint,
+            redis_consumer_health: redis_consumer_health.clone(),
+            redis_pool: redis_pool.clone(),
+            store_latencies,
+            store_task_health: store_task_health.clone(),
+            stored_events_tx: stored_events_tx.clone(),
+        },
         submissions_rx,
     );
 
-    let server_thread = run_server_thread(redis_health, redis_consumer_health, shutdown_notify);
+    let server_thread = run_server_thread(
+        AppState {
+            block_counter,
+            consumer_task_health,
+            redis_health,
+            redis_consumer_health,
+            redis_pool,
+            store_task_health,
+            stored_events_tx,
+        },
+        shutdown_notify,
+    );
 
     try_join!(
+        archive_submissions_thread,
+        backfill_submissions_thread,
         cache_submissions_thread,
+        checkpoint_thread,
         log_block_counter_thread,
+        reclaim_submissions_thread,
         server_thread,
         store_submissions_thread,
+        stream_info_thread,
     )?;
 
     Ok(())
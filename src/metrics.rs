@@ -0,0 +1,162 @@
+//! # Metrics
+//!
+//! Prometheus metrics, served on /metrics.
+use axum::{
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use lazy_static::lazy_static;
+use prometheus::{
+    exponential_buckets, register_gauge_vec, register_histogram, register_int_counter,
+    register_int_counter_vec, register_int_gauge, register_int_gauge_vec, Encoder, GaugeVec,
+    Histogram, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+use tracing::error;
+
+lazy_static! {
+    pub static ref SUBMISSIONS_READ: IntCounterVec = register_int_counter_vec!(
+        "submissions_read_total",
+        "Stream entries read.",
+        &["stream"]
+    )
+    .unwrap();
+    pub static ref SUBMISSIONS_SKIPPED: IntCounterVec = register_int_counter_vec!(
+        "submissions_skipped_total",
+        "Submissions not stored, by reason.",
+        &["stream", "reason"]
+    )
+    .unwrap();
+    pub static ref DECODE_FAILURES: IntCounterVec = register_int_counter_vec!(
+        "submissions_decode_failures_total",
+        "Stream entries we failed to decode and sent to the dead-letter stream.",
+        &["stream"]
+    )
+    .unwrap();
+    pub static ref PAYLOADS_STORED: IntCounterVec = register_int_counter_vec!(
+        "payloads_stored_total",
+        "Payloads stored for the relay.",
+        &["stream", "fork"]
+    )
+    .unwrap();
+    pub static ref REDIS_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "redis_errors_total",
+        "Failed Redis commands.",
+        &["command"]
+    )
+    .unwrap();
+    pub static ref ARCHIVE_OBJECTS_UPLOADED: IntCounter = register_int_counter!(
+        "archive_objects_uploaded_total",
+        "Archive objects uploaded to the object store."
+    )
+    .unwrap();
+    pub static ref ARCHIVE_SUBMISSIONS_ARCHIVED: IntCounter = register_int_counter!(
+        "archive_submissions_archived_total",
+        "Submissions in uploaded archive objects."
+    )
+    .unwrap();
+    pub static ref ARCHIVE_SUBMISSIONS_DROPPED: IntCounter = register_int_counter!(
+        "archive_submissions_dropped_total",
+        "Submissions not archived, because the archiver fell behind or an upload kept failing."
+    )
+    .unwrap();
+    pub static ref ARCHIVE_UPLOAD_FAILURES: IntCounter = register_int_counter!(
+        "archive_upload_failures_total",
+        "Archive objects given up on after retrying."
+    )
+    .unwrap();
+    pub static ref BEST_BID_CHANGES: IntCounter = register_int_counter!(
+        "best_bid_changes_total",
+        "Times the best bid for a slot, parent hash and proposer changed."
+    )
+    .unwrap();
+    pub static ref CHANNEL_DEPTH: IntGauge = register_int_gauge!(
+        "submissions_channel_depth",
+        "Submissions waiting in the channel between consumer and storage."
+    )
+    .unwrap();
+    pub static ref CONSUMER_LAG: GaugeVec = register_gauge_vec!(
+        "consumer_lag_seconds",
+        "Time since the newest entry we read from the stream was added, zero when caught up.",
+        &["stream"]
+    )
+    .unwrap();
+    pub static ref STREAM_LENGTH: IntGaugeVec = register_int_gauge_vec!(
+        "stream_length",
+        "Entries in the stream, as reported by XINFO STREAM.",
+        &["stream"]
+    )
+    .unwrap();
+    pub static ref STORED_EVENTS_MISSED: IntCounter = register_int_counter!(
+        "stored_events_missed_total",
+        "Stored events dropped for subscribers which fell behind."
+    )
+    .unwrap();
+    // 1KiB up to 8MiB.
+    pub static ref PAYLOAD_SIZE: Histogram = register_histogram!(
+        "payload_size_bytes",
+        "Size of the stored payloads.",
+        exponential_buckets(1024.0, 2.0, 14).unwrap()
+    )
+    .unwrap();
+    // 1ms up to about 16s.
+    pub static ref RECEIVE_TO_STORED: Histogram = register_histogram!(
+        "receive_to_stored_seconds",
+        "Time from the relay receiving a block submission to us storing it.",
+        exponential_buckets(0.001, 2.0, 15).unwrap()
+    )
+    .unwrap();
+    pub static ref ELIGIBLE_TO_STORED: Histogram = register_histogram!(
+        "eligible_to_stored_seconds",
+        "Time from a block submission becoming eligible to us storing it.",
+        exponential_buckets(0.001, 2.0, 15).unwrap()
+    )
+    .unwrap();
+    // 0.5ms up to about 4s.
+    pub static ref STORE_DURATION: Histogram = register_histogram!(
+        "store_duration_seconds",
+        "Time taken to store a payload in Redis.",
+        exponential_buckets(0.0005, 2.0, 14).unwrap()
+    )
+    .unwrap();
+}
+
+/// Counts a failed Redis command, use as `.map_err(metrics::redis_error("xread"))`.
+pub fn redis_error<E>(command: &'static str) -> impl FnOnce(E) -> E {
+    move |e| {
+        REDIS_ERRORS.with_label_values(&[command]).inc();
+        e
+    }
+}
+
+pub async fn get_metrics() -> impl IntoResponse {
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    match encoder.encode(&prometheus::gather(), &mut buffer) {
+        Ok(()) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, encoder.format_type().to_string())],
+            buffer,
+        ),
+        Err(e) => {
+            error!(?e, "failed to encode metrics");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                [(header::CONTENT_TYPE, "text/plain".to_string())],
+                Vec::new(),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redis_error_counts_command() {
+        let before = REDIS_ERRORS.with_label_values(&["test"]).get();
+        let result: Result<(), &str> = Err("boom");
+        let _ = result.map_err(redis_error("test"));
+        assert_eq!(REDIS_ERRORS.with_label_values(&["test"]).get(), before + 1);
+    }
+}
@@ -0,0 +1,214 @@
+//! # Network
+//!
+//! The networks we know how to run on. Public networks are built in, private devnets can be added
+//! through a JSON file at NETWORK_CONFIG_PATH, holding a list of networks like:
+//!
+//! ```json
+//! [
+//!   {
+//!     "name": "devnet-7",
+//!     "genesis_time": 1710000000,
+//!     "seconds_per_slot": 6,
+//!     "fork_schedule": { "capella_epoch": 0, "deneb_epoch": 0, "electra_epoch": 10 }
+//!   }
+//! ]
+//! ```
+//!
+//! Names have to be lowercase and slots have to last at least a second.
+use std::fmt::Display;
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use crate::fork::ForkSchedule;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct Network {
+    /// Also used in the key prefix the relay reads payloads from.
+    pub name: String,
+    /// Seconds since the unix epoch.
+    pub genesis_time: u64,
+    pub seconds_per_slot: u64,
+    pub fork_schedule: ForkSchedule,
+}
+
+impl Display for Network {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+fn builtin_networks() -> Vec<Network> {
+    vec![
+        Network {
+            name: "mainnet".to_string(),
+            genesis_time: 1606824023,
+            seconds_per_slot: 12,
+            fork_schedule: ForkSchedule {
+                capella_epoch: 194048,
+                deneb_epoch: Some(269568),
+                electra_epoch: Some(364032),
+            },
+        },
+        Network {
+            name: "holesky".to_string(),
+            genesis_time: 1695902400,
+            seconds_per_slot: 12,
+            fork_schedule: ForkSchedule {
+                capella_epoch: 256,
+                deneb_epoch: Some(29696),
+                electra_epoch: Some(115968),
+            },
+        },
+        Network {
+            name: "sepolia".to_string(),
+            genesis_time: 1655733600,
+            seconds_per_slot: 12,
+            fork_schedule: ForkSchedule {
+                capella_epoch: 56832,
+                deneb_epoch: Some(132608),
+                electra_epoch: Some(222464),
+            },
+        },
+        Network {
+            name: "goerli".to_string(),
+            genesis_time: 1616508000,
+            seconds_per_slot: 12,
+            fork_schedule: ForkSchedule {
+                capella_epoch: 162304,
+                deneb_epoch: Some(231680),
+                electra_epoch: None,
+            },
+        },
+    ]
+}
+
+// NETWORK is lowercased before we look it up, a name with uppercase letters could never be
+// selected. A slot clock can't divide time into zero second slots.
+fn validate(network: &Network) -> Result<()> {
+    if network.name != network.name.to_lowercase() {
+        bail!("network name {} is not lowercase", network.name);
+    }
+    if network.seconds_per_slot == 0 {
+        bail!("network {} has zero seconds_per_slot", network.name);
+    }
+    Ok(())
+}
+
+fn read_custom_networks(path: &str) -> Result<Vec<Network>> {
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("failed to open network config {path}"))?;
+    let networks: Vec<Network> = serde_json::from_reader(file)
+        .with_context(|| format!("failed to parse network config {path}"))?;
+    for network in &networks {
+        validate(network).with_context(|| format!("invalid network config {path}"))?;
+    }
+    Ok(networks)
+}
+
+/// All networks we know, the built in ones followed by those in the custom network config, if
+/// any. A custom network with the name of a built in one replaces it.
+pub fn registry(custom_networks_path: Option<&str>) -> Result<Vec<Network>> {
+    let mut networks = builtin_networks();
+    if let Some(path) = custom_networks_path {
+        for network in read_custom_networks(path)? {
+            networks.retain(|known| known.name != network.name);
+            networks.push(network);
+        }
+    }
+    Ok(networks)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn builtin_network_names_are_unique() {
+        let networks = builtin_networks();
+        for network in &networks {
+            assert_eq!(
+                networks.iter().filter(|n| n.name == network.name).count(),
+                1
+            );
+        }
+    }
+
+    fn write_network_config(name: &str, contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("block-submission-service-{name}.json"));
+        let mut file = std::fs::File::create(&path).unwrap();
+        write!(file, "{contents}").unwrap();
+        path
+    }
+
+    #[test]
+    fn registry_adds_custom_networks() {
+        let path = write_network_config(
+            "networks-test",
+            r#"[
+                {
+                    "name": "devnet-7",
+                    "genesis_time": 1710000000,
+                    "seconds_per_slot": 6,
+                    "fork_schedule": { "capella_epoch": 0, "deneb_epoch": 0, "electra_epoch": null }
+                },
+                {
+                    "name": "sepolia",
+                    "genesis_time": 1,
+                    "seconds_per_slot": 12,
+                    "fork_schedule": { "capella_epoch": 0, "deneb_epoch": null, "electra_epoch": null }
+                }
+            ]"#,
+        );
+
+        let networks = registry(path.to_str()).unwrap();
+        let devnet = networks.iter().find(|n| n.name == "devnet-7").unwrap();
+        assert_eq!(devnet.seconds_per_slot, 6);
+        assert_eq!(devnet.fork_schedule.deneb_epoch, Some(0));
+
+        let sepolia: Vec<_> = networks.iter().filter(|n| n.name == "sepolia").collect();
+        assert_eq!(sepolia.len(), 1);
+        assert_eq!(sepolia[0].genesis_time, 1);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn registry_rejects_zero_seconds_per_slot() {
+        let path = write_network_config(
+            "networks-zero-slot-test",
+            r#"[{
+                "name": "devnet-7",
+                "genesis_time": 1710000000,
+                "seconds_per_slot": 0,
+                "fork_schedule": { "capella_epoch": 0, "deneb_epoch": null, "electra_epoch": null }
+            }]"#,
+        );
+        assert!(registry(path.to_str()).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn registry_rejects_names_which_are_not_lowercase() {
+        let path = write_network_config(
+            "networks-uppercase-test",
+            r#"[{
+                "name": "MyDevnet",
+                "genesis_time": 1710000000,
+                "seconds_per_slot": 6,
+                "fork_schedule": { "capella_epoch": 0, "deneb_epoch": null, "electra_epoch": null }
+            }]"#,
+        );
+        assert!(registry(path.to_str()).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn builtin_networks_are_valid() {
+        for network in builtin_networks() {
+            validate(&network).unwrap();
+        }
+    }
+}
@@ -0,0 +1,437 @@
+//! # Payload
+//!
+//! Typed models for the builder-spec block submission payload, `SignedBidSubmission`. Hex fields
+//! are checked for their exact length, quantities are decimal strings that have to fit their
+//! integer type.
+//!
+//! The service itself only decodes the `BidTrace` message on the hot path, the execution payload
+//! is passed through as raw bytes. Decoding it is available for tooling which needs it.
+use std::{fmt::Display, str::FromStr};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Quantities are sent as decimal strings.
+pub(crate) mod quantity {
+    use super::*;
+
+    pub fn serialize<T: Display, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        let str = String::deserialize(deserializer)?;
+        str.parse::<T>()
+            .map_err(|e| de::Error::custom(format!("invalid quantity {str}, {e}")))
+    }
+}
+
+fn strip_hex_prefix(str: &str) -> Result<&str, String> {
+    str.strip_prefix("0x")
+        .ok_or_else(|| format!("expected hex string to start with 0x, got {str}"))
+}
+
+/// Hex encoded bytes of a fixed length.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FixedHex<const N: usize>(pub [u8; N]);
+
+pub type Address = FixedHex<20>;
+pub type Bloom = FixedHex<256>;
+pub type BlsPublicKey = FixedHex<48>;
+pub type BlsSignature = FixedHex<96>;
+pub type Hash32 = FixedHex<32>;
+pub type KzgCommitment = FixedHex<48>;
+pub type KzgProof = FixedHex<48>;
+
+impl<const N: usize> FromStr for FixedHex<N> {
+    type Err = String;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        let mut bytes = [0u8; N];
+        hex::decode_to_slice(strip_hex_prefix(str)?, &mut bytes)
+            .map_err(|e| format!("expected {N} hex encoded bytes, got {str}, {e}"))?;
+        Ok(Self(bytes))
+    }
+}
+
+impl<const N: usize> Display for FixedHex<N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "0x{}", hex::encode(self.0))
+    }
+}
+
+impl<const N: usize> std::fmt::Debug for FixedHex<N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl<const N: usize> Serialize for FixedHex<N> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de, const N: usize> Deserialize<'de> for FixedHex<N> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let str = String::deserialize(deserializer)?;
+        str.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Hex encoded bytes of any length.
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct HexBytes(pub Vec<u8>);
+
+impl Display for HexBytes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "0x{}", hex::encode(&self.0))
+    }
+}
+
+impl std::fmt::Debug for HexBytes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl Serialize for HexBytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for HexBytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let str = String::deserialize(deserializer)?;
+        let hex = strip_hex_prefix(&str).map_err(de::Error::custom)?;
+        hex::decode(hex)
+            .map(HexBytes)
+            .map_err(|e| de::Error::custom(format!("invalid hex string {str}, {e}")))
+    }
+}
+
+const MAX_EXTRA_DATA_BYTES: usize = 32;
+
+fn deserialize_extra_data<'de, D>(deserializer: D) -> Result<HexBytes, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let extra_data = HexBytes::deserialize(deserializer)?;
+    if extra_data.0.len() > MAX_EXTRA_DATA_BYTES {
+        return Err(de::Error::custom(format!(
+            "extra_data is {} bytes, max is {MAX_EXTRA_DATA_BYTES}",
+            extra_data.0.len()
+        )));
+    }
+    Ok(extra_data)
+}
+
+const BYTES_PER_BLOB: usize = 131072;
+
+fn deserialize_blobs<'de, D>(deserializer: D) -> Result<Vec<HexBytes>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let blobs = Vec::<HexBytes>::deserialize(deserializer)?;
+    if let Some(blob) = blobs.iter().find(|blob| blob.0.len() != BYTES_PER_BLOB) {
+        return Err(de::Error::custom(format!(
+            "blob is {} bytes, expected {BYTES_PER_BLOB}",
+            blob.0.len()
+        )));
+    }
+    Ok(blobs)
+}
+
+/// The bid, the `message` of a block submission.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct BidTrace {
+    #[serde(with = "quantity")]
+    pub slot: u64,
+    pub parent_hash: Hash32,
+    pub block_hash: Hash32,
+    pub builder_pubkey: BlsPublicKey,
+    pub proposer_pubkey: BlsPublicKey,
+    pub proposer_fee_recipient: Address,
+    #[serde(with = "quantity")]
+    pub gas_limit: u64,
+    #[serde(with = "quantity")]
+    pub gas_used: u64,
+    /// The bid value in wei.
+    #[serde(with = "quantity")]
+    pub value: u128,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Withdrawal {
+    #[serde(with = "quantity")]
+    pub index: u64,
+    #[serde(with = "quantity")]
+    pub validator_index: u64,
+    pub address: Address,
+    /// The amount in gwei.
+    #[serde(with = "quantity")]
+    pub amount: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ExecutionPayloadCapella {
+    pub parent_hash: Hash32,
+    pub fee_recipient: Address,
+    pub state_root: Hash32,
+    pub receipts_root: Hash32,
+    pub logs_bloom: Bloom,
+    pub prev_randao: Hash32,
+    #[serde(with = "quantity")]
+    pub block_number: u64,
+    #[serde(with = "quantity")]
+    pub gas_limit: u64,
+    #[serde(with = "quantity")]
+    pub gas_used: u64,
+    #[serde(with = "quantity")]
+    pub timestamp: u64,
+    #[serde(deserialize_with = "deserialize_extra_data")]
+    pub extra_data: HexBytes,
+    #[serde(with = "quantity")]
+    pub base_fee_per_gas: u128,
+    pub block_hash: Hash32,
+    pub transactions: Vec<HexBytes>,
+    pub withdrawals: Vec<Withdrawal>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ExecutionPayloadDeneb {
+    pub parent_hash: Hash32,
+    pub fee_recipient: Address,
+    pub state_root: Hash32,
+    pub receipts_root: Hash32,
+    pub logs_bloom: Bloom,
+    pub prev_randao: Hash32,
+    #[serde(with = "quantity")]
+    pub block_number: u64,
+    #[serde(with = "quantity")]
+    pub gas_limit: u64,
+    #[serde(with = "quantity")]
+    pub gas_used: u64,
+    #[serde(with = "quantity")]
+    pub timestamp: u64,
+    #[serde(deserialize_with = "deserialize_extra_data")]
+    pub extra_data: HexBytes,
+    #[serde(with = "quantity")]
+    pub base_fee_per_gas: u128,
+    pub block_hash: Hash32,
+    pub transactions: Vec<HexBytes>,
+    pub withdrawals: Vec<Withdrawal>,
+    #[serde(with = "quantity")]
+    pub blob_gas_used: u64,
+    #[serde(with = "quantity")]
+    pub excess_blob_gas: u64,
+}
+
+/// Deneb payloads are Capella payloads plus the blob gas fields, we try Deneb first.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum ExecutionPayload {
+    Deneb(ExecutionPayloadDeneb),
+    Capella(ExecutionPayloadCapella),
+}
+
+impl ExecutionPayload {
+    pub fn block_hash(&self) -> &Hash32 {
+        match self {
+            ExecutionPayload::Capella(payload) => &payload.block_hash,
+            ExecutionPayload::Deneb(payload) => &payload.block_hash,
+        }
+    }
+
+    pub fn state_root(&self) -> &Hash32 {
+        match self {
+            ExecutionPayload::Capella(payload) => &payload.state_root,
+            ExecutionPayload::Deneb(payload) => &payload.state_root,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct BlobsBundle {
+    pub commitments: Vec<KzgCommitment>,
+    pub proofs: Vec<KzgProof>,
+    #[serde(deserialize_with = "deserialize_blobs")]
+    pub blobs: Vec<HexBytes>,
+}
+
+/// What the relay stores, and serves, for a Deneb block.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ExecutionPayloadAndBlobsBundle {
+    pub execution_payload: ExecutionPayloadDeneb,
+    pub blobs_bundle: BlobsBundle,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct DepositRequest {
+    pub pubkey: BlsPublicKey,
+    pub withdrawal_credentials: Hash32,
+    /// The amount in gwei.
+    #[serde(with = "quantity")]
+    pub amount: u64,
+    pub signature: BlsSignature,
+    #[serde(with = "quantity")]
+    pub index: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct WithdrawalRequest {
+    pub source_address: Address,
+    pub validator_pubkey: BlsPublicKey,
+    /// The amount in gwei.
+    #[serde(with = "quantity")]
+    pub amount: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ConsolidationRequest {
+    pub source_address: Address,
+    pub source_pubkey: BlsPublicKey,
+    pub target_pubkey: BlsPublicKey,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ExecutionRequests {
+    pub deposits: Vec<DepositRequest>,
+    pub withdrawals: Vec<WithdrawalRequest>,
+    pub consolidations: Vec<ConsolidationRequest>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct SignedBidSubmission {
+    pub message: BidTrace,
+    pub execution_payload: ExecutionPayload,
+    /// Only present from Deneb onwards.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blobs_bundle: Option<BlobsBundle>,
+    /// Only present from Electra onwards.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub execution_requests: Option<ExecutionRequests>,
+    pub signature: BlsSignature,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const FIXTURE_PATH: &str = "tests/fixtures/0xffe314e3f12d726cf9f4a4babfcbfc836ef53d3144469f886423a833c853e3ef.json.gz.decompressed";
+
+    fn bid_trace_json() -> serde_json::Value {
+        json!({
+            "slot": "7323900",
+            "parent_hash": "0xa82c987560729d49754f66858d7f25f460908d5477087cd701aaf5920ad305cd",
+            "block_hash": "0xe2b6337a01144887af67f9b2cfb77df39d62033fedcfd3cb70d45035ff79ae3b",
+            "builder_pubkey": "0x9192ca93e6c9b499d730a153e3dfba05bc13a91d50750265793de1f2488a66a7f3232f7b1ef374150365b9ea2cf9824d",
+            "proposer_pubkey": "0x8567a285bb5eee7373fb0f71df1f5a3367f6fa3fdf4a4931f51cd8bf56c00d6ce77836051ea1f797a21f9bd7b12275fd",
+            "proposer_fee_recipient": "0x388C818CA8B9251b393131C08a736A67ccB19297",
+            "gas_limit": "29999972",
+            "gas_used": "6635538",
+            "value": "13620198421388901"
+        })
+    }
+
+    #[test]
+    fn decode_bid_trace() {
+        let bid_trace: BidTrace = serde_json::from_value(bid_trace_json()).unwrap();
+        assert_eq!(bid_trace.slot, 7323900);
+        assert_eq!(bid_trace.value, 13620198421388901);
+        assert_eq!(
+            bid_trace.proposer_fee_recipient.to_string(),
+            "0x388c818ca8b9251b393131c08a736a67ccb19297"
+        );
+    }
+
+    #[test]
+    fn reject_bad_hex_lengths() {
+        let mut short_hash = bid_trace_json();
+        short_hash["block_hash"] = json!("0xe2b6");
+        assert!(serde_json::from_value::<BidTrace>(short_hash).is_err());
+
+        let mut missing_prefix = bid_trace_json();
+        missing_prefix["block_hash"] =
+            json!("e2b6337a01144887af67f9b2cfb77df39d62033fedcfd3cb70d45035ff79ae3b");
+        assert!(serde_json::from_value::<BidTrace>(missing_prefix).is_err());
+    }
+
+    #[test]
+    fn reject_bad_quantities() {
+        let mut negative_slot = bid_trace_json();
+        negative_slot["slot"] = json!("-1");
+        assert!(serde_json::from_value::<BidTrace>(negative_slot).is_err());
+
+        let mut number_value = bid_trace_json();
+        number_value["value"] = json!(1);
+        assert!(serde_json::from_value::<BidTrace>(number_value).is_err());
+    }
+
+    #[test]
+    fn decode_capella_fixture() {
+        let file = std::fs::File::open(FIXTURE_PATH).unwrap();
+        let archive_entry: serde_json::Value = serde_json::from_reader(file).unwrap();
+        let submission: SignedBidSubmission =
+            serde_json::from_value(archive_entry["payload"].clone()).unwrap();
+
+        assert!(matches!(
+            submission.execution_payload,
+            ExecutionPayload::Capella(_)
+        ));
+        assert_eq!(
+            submission.execution_payload.block_hash(),
+            &submission.message.block_hash
+        );
+        assert_eq!(
+            submission.execution_payload.state_root().to_string(),
+            "0xffe314e3f12d726cf9f4a4babfcbfc836ef53d3144469f886423a833c853e3ef"
+        );
+    }
+
+    #[test]
+    fn decode_deneb_payload() {
+        let file = std::fs::File::open(FIXTURE_PATH).unwrap();
+        let archive_entry: serde_json::Value = serde_json::from_reader(file).unwrap();
+        let mut execution_payload = archive_entry["payload"]["execution_payload"].clone();
+        execution_payload["blob_gas_used"] = json!("131072");
+        execution_payload["excess_blob_gas"] = json!("0");
+
+        let execution_payload: ExecutionPayload =
+            serde_json::from_value(execution_payload).unwrap();
+        match execution_payload {
+            ExecutionPayload::Deneb(payload) => assert_eq!(payload.blob_gas_used, 131072),
+            ExecutionPayload::Capella(_) => panic!("expected a deneb payload"),
+        }
+    }
+
+    #[test]
+    fn decode_blobs_bundle() {
+        let commitment = format!("0x{}", "ab".repeat(48));
+        let blob = format!("0x{}", "00".repeat(BYTES_PER_BLOB));
+        let blobs_bundle: BlobsBundle = serde_json::from_value(json!({
+            "commitments": [commitment],
+            "proofs": [commitment],
+            "blobs": [blob]
+        }))
+        .unwrap();
+        assert_eq!(blobs_bundle.blobs[0].0.len(), BYTES_PER_BLOB);
+
+        let short_blob = serde_json::from_value::<BlobsBundle>(json!({
+            "commitments": [],
+            "proofs": [],
+            "blobs": ["0x00"]
+        }));
+        assert!(short_blob.is_err());
+    }
+}
@@ -1,13 +1,137 @@
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
 use std::time::{Duration, Instant};
+
+use axum::{extract::State, Json};
+use serde::Serialize;
 use tokio::time::interval;
 use tracing::info;
 
-// Count the number of blocks stored.
+use crate::server::AppState;
+
+// The windows we report throughput over, with their length in seconds.
+const WINDOWS: [(&str, u64); 3] = [("10s", 10), ("1m", 60), ("5m", 300)];
+
+// Per second buckets are kept for the longest window.
+const MAX_WINDOW_SECS: u64 = 300;
+
+// How many of the most recent slots we keep counts for.
+const TRACKED_SLOTS: usize = 32;
+
+// How many of the most recent slots we log counts for. The latest slot is likely still in
+// progress, the one before is complete.
+const LOGGED_SLOTS: usize = 2;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Counts {
+    pub read: u64,
+    pub skipped: u64,
+    pub stored: u64,
+}
+
+impl Counts {
+    fn add(&mut self, other: &Counts) {
+        self.read += other.read;
+        self.skipped += other.skipped;
+        self.stored += other.stored;
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Event {
+    Read,
+    Skipped,
+    Stored,
+}
+
+impl Event {
+    fn count(&self, counts: &mut Counts) {
+        match self {
+            Event::Read => counts.read += 1,
+            Event::Skipped => counts.skipped += 1,
+            Event::Stored => counts.stored += 1,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct WindowStats {
+    #[serde(flatten)]
+    pub counts: Counts,
+    pub stored_per_second: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    pub total: Counts,
+    pub windows: BTreeMap<&'static str, WindowStats>,
+    pub slots: BTreeMap<u64, Counts>,
+}
+
+impl Stats {
+    // The counts of the most recent slots, latest first.
+    fn latest_slots(&self, count: usize) -> impl Iterator<Item = (&u64, &Counts)> {
+        self.slots.iter().rev().take(count)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    // Counts per second since start, oldest first.
+    seconds: VecDeque<(u64, Counts)>,
+    slots: BTreeMap<u64, Counts>,
+}
+
+impl Buckets {
+    fn record(&mut self, second: u64, event: Event, slot: Option<u64>) {
+        match self.seconds.back_mut() {
+            Some((last_second, counts)) if *last_second == second => event.count(counts),
+            _ => {
+                let mut counts = Counts::default();
+                event.count(&mut counts);
+                self.seconds.push_back((second, counts));
+            }
+        }
+        while self
+            .seconds
+            .front()
+            .is_some_and(|(oldest, _)| oldest + MAX_WINDOW_SECS <= second)
+        {
+            self.seconds.pop_front();
+        }
+
+        if let Some(slot) = slot {
+            event.count(self.slots.entry(slot).or_default());
+            while self.slots.len() > TRACKED_SLOTS {
+                self.slots.pop_first();
+            }
+        }
+    }
+
+    fn window(&self, second: u64, length: u64) -> Counts {
+        let mut counts = Counts::default();
+        for (_, bucket_counts) in self
+            .seconds
+            .iter()
+            .filter(|(bucket_second, _)| bucket_second + length > second)
+        {
+            counts.add(bucket_counts);
+        }
+        counts
+    }
+}
+
+// Counts block submissions read, skipped and stored, in total, over rolling windows, and for the
+// most recent slots.
 #[derive(Debug)]
 pub struct BlockCounter {
-    count: AtomicU32,
+    buckets: Mutex<Buckets>,
+    last_stored_on: Mutex<Option<Instant>>,
     started_on: Instant,
+    total_read: AtomicU64,
+    total_skipped: AtomicU64,
+    total_stored: AtomicU64,
 }
 
 impl Default for BlockCounter {
@@ -19,34 +143,298 @@ impl Default for BlockCounter {
 impl BlockCounter {
     pub fn new() -> Self {
         Self {
-            count: AtomicU32::new(0),
+            buckets: Mutex::new(Buckets::default()),
+            last_stored_on: Mutex::new(None),
             started_on: Instant::now(),
+            total_read: AtomicU64::new(0),
+            total_skipped: AtomicU64::new(0),
+            total_stored: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, event: Event, slot: Option<u64>) {
+        let total = match event {
+            Event::Read => &self.total_read,
+            Event::Skipped => &self.total_skipped,
+            Event::Stored => &self.total_stored,
+        };
+        total.fetch_add(1, Ordering::Relaxed);
+
+        let second = self.started_on.elapsed().as_secs();
+        self.buckets
+            .lock()
+            .expect("expect to be able to acquire buckets lock")
+            .record(second, event, slot);
+    }
+
+    pub fn record_read(&self, slot: Option<u64>) {
+        self.record(Event::Read, slot);
+    }
+
+    pub fn record_skipped(&self, slot: Option<u64>) {
+        self.record(Event::Skipped, slot);
+    }
+
+    pub fn record_stored(&self, slot: u64) {
+        self.record(Event::Stored, Some(slot));
+        self.last_stored_on
+            .lock()
+            .expect("expect to be able to acquire last stored lock")
+            .replace(Instant::now());
+    }
+
+    pub fn time_since_last_stored(&self) -> Option<Duration> {
+        self.last_stored_on
+            .lock()
+            .expect("expect to be able to acquire last stored lock")
+            .map(|instant| instant.elapsed())
+    }
+
+    pub fn time_since_start(&self) -> Duration {
+        self.started_on.elapsed()
+    }
+
+    pub fn stats(&self) -> Stats {
+        let elapsed_secs = self.started_on.elapsed().as_secs();
+        let buckets = self
+            .buckets
+            .lock()
+            .expect("expect to be able to acquire buckets lock");
+
+        let windows = WINDOWS
+            .iter()
+            .map(|(name, length)| {
+                let counts = buckets.window(elapsed_secs, *length);
+                // Right after starting, the window is only as long as we've been running.
+                let seconds = (*length).min(elapsed_secs + 1);
+                let stats = WindowStats {
+                    counts,
+                    stored_per_second: counts.stored as f64 / seconds as f64,
+                };
+                (*name, stats)
+            })
+            .collect();
+
+        Stats {
+            total: Counts {
+                read: self.total_read.load(Ordering::Relaxed),
+                skipped: self.total_skipped.load(Ordering::Relaxed),
+                stored: self.total_stored.load(Ordering::Relaxed),
+            },
+            windows,
+            slots: buckets.slots.clone(),
+        }
+    }
+
+    pub fn log(&self) {
+        let stats = self.stats();
+        let window = |name: &str| &stats.windows[name];
+        info!(
+            total_stored = stats.total.stored,
+            stored_per_second_10s = window("10s").stored_per_second,
+            stored_per_second_1m = window("1m").stored_per_second,
+            stored_per_second_5m = window("5m").stored_per_second,
+            read_1m = window("1m").counts.read,
+            skipped_1m = window("1m").counts.skipped,
+            stored_1m = window("1m").counts.stored,
+            "block submission stored rate"
+        );
+        for (slot, counts) in stats.latest_slots(LOGGED_SLOTS) {
+            info!(
+                slot,
+                read = counts.read,
+                skipped = counts.skipped,
+                stored = counts.stored,
+                "block submissions for slot"
+            );
         }
     }
+}
+
+pub async fn get_stats(State(state): State<AppState>) -> Json<Stats> {
+    Json(state.block_counter.stats())
+}
+
+// We only need enough samples per report for decent percentiles.
+const MAX_LATENCY_SAMPLES: usize = 16_384;
+
+// Nearest-rank percentile of sorted samples.
+fn percentile(sorted: &[u64], percentile: f64) -> Option<u64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+fn take_sorted(samples: &Mutex<Vec<u64>>) -> Vec<u64> {
+    let mut samples = std::mem::take(
+        &mut *samples
+            .lock()
+            .expect("expect to be able to acquire latencies lock"),
+    );
+    samples.sort_unstable();
+    samples
+}
+
+fn push_sample(samples: &Mutex<Vec<u64>>, sample: u64) {
+    let mut samples = samples
+        .lock()
+        .expect("expect to be able to acquire latencies lock");
+    if samples.len() < MAX_LATENCY_SAMPLES {
+        samples.push(sample);
+    }
+}
+
+// Latencies in milliseconds from a block submission being received, or becoming eligible, to it
+// being stored. Collected between reports.
+#[derive(Debug, Default)]
+pub struct StoreLatencies {
+    eligible_to_stored: Mutex<Vec<u64>>,
+    receive_to_stored: Mutex<Vec<u64>>,
+}
 
-    pub fn increment(&self) {
-        self.count.fetch_add(1, Ordering::Relaxed);
+impl StoreLatencies {
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    // Format a pretty message showing our block submissions stored per second.
-    fn per_second(&self) -> f64 {
-        let elapsed = self.started_on.elapsed();
-        let seconds = elapsed.as_secs_f64();
-        let count = self.count.load(Ordering::Relaxed);
-        count as f64 / seconds
+    pub fn record(&self, receive_to_stored_ms: u64, eligible_to_stored_ms: Option<u64>) {
+        push_sample(&self.receive_to_stored, receive_to_stored_ms);
+        if let Some(eligible_to_stored_ms) = eligible_to_stored_ms {
+            push_sample(&self.eligible_to_stored, eligible_to_stored_ms);
+        }
     }
 
     pub fn log(&self) {
-        let count = self.count.load(Ordering::Relaxed);
-        let per_second = self.per_second();
-        info!(count, per_second, "block submission stored rate");
+        let receive_to_stored = take_sorted(&self.receive_to_stored);
+        let eligible_to_stored = take_sorted(&self.eligible_to_stored);
+        info!(
+            count = receive_to_stored.len(),
+            receive_p50_ms = percentile(&receive_to_stored, 50.0),
+            receive_p95_ms = percentile(&receive_to_stored, 95.0),
+            receive_p99_ms = percentile(&receive_to_stored, 99.0),
+            eligible_p50_ms = percentile(&eligible_to_stored, 50.0),
+            eligible_p95_ms = percentile(&eligible_to_stored, 95.0),
+            eligible_p99_ms = percentile(&eligible_to_stored, 99.0),
+            "block submission store latency"
+        );
     }
 }
 
-pub async fn report_storage_rate_periodically(block_counter: &BlockCounter) {
+pub async fn report_storage_rate_periodically(
+    block_counter: &BlockCounter,
+    store_latencies: &StoreLatencies,
+) {
     let mut interval = interval(Duration::from_secs(8));
     loop {
         interval.tick().await;
         block_counter.log();
+        store_latencies.log();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_only_count_recent_seconds() {
+        let mut buckets = Buckets::default();
+        buckets.record(0, Event::Stored, Some(1));
+        buckets.record(55, Event::Read, Some(5));
+        buckets.record(55, Event::Stored, Some(5));
+        buckets.record(60, Event::Skipped, None);
+
+        assert_eq!(
+            buckets.window(60, 10),
+            Counts {
+                read: 1,
+                skipped: 1,
+                stored: 1
+            }
+        );
+        assert_eq!(buckets.window(60, 60).stored, 1);
+        assert_eq!(buckets.window(60, 300).stored, 2);
+    }
+
+    #[test]
+    fn old_buckets_are_dropped() {
+        let mut buckets = Buckets::default();
+        buckets.record(0, Event::Stored, None);
+        buckets.record(MAX_WINDOW_SECS, Event::Stored, None);
+        assert_eq!(buckets.seconds.len(), 1);
+        assert_eq!(buckets.window(MAX_WINDOW_SECS, MAX_WINDOW_SECS).stored, 1);
+    }
+
+    #[test]
+    fn only_recent_slots_are_tracked() {
+        let mut buckets = Buckets::default();
+        for slot in 0..(TRACKED_SLOTS as u64 + 8) {
+            buckets.record(0, Event::Stored, Some(slot));
+        }
+        assert_eq!(buckets.slots.len(), TRACKED_SLOTS);
+        assert_eq!(buckets.slots.keys().next(), Some(&8));
+    }
+
+    #[test]
+    fn latest_slots_come_first() {
+        let block_counter = BlockCounter::new();
+        block_counter.record_read(Some(10));
+        block_counter.record_stored(10);
+        block_counter.record_read(Some(11));
+        block_counter.record_skipped(Some(11));
+        block_counter.record_read(Some(9));
+
+        let stats = block_counter.stats();
+        let latest: Vec<(&u64, &Counts)> = stats.latest_slots(LOGGED_SLOTS).collect();
+        assert_eq!(
+            latest,
+            vec![
+                (
+                    &11,
+                    &Counts {
+                        read: 1,
+                        skipped: 1,
+                        stored: 0
+                    }
+                ),
+                (
+                    &10,
+                    &Counts {
+                        read: 1,
+                        skipped: 0,
+                        stored: 1
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn percentile_test() {
+        let samples: Vec<u64> = (1..=100).collect();
+        assert_eq!(percentile(&samples, 50.0), Some(50));
+        assert_eq!(percentile(&samples, 95.0), Some(95));
+        assert_eq!(percentile(&samples, 99.0), Some(99));
+        assert_eq!(percentile(&[7], 99.0), Some(7));
+        assert_eq!(percentile(&[], 50.0), None);
+    }
+
+    #[test]
+    fn log_drains_samples() {
+        let store_latencies = StoreLatencies::new();
+        store_latencies.record(10, Some(5));
+        store_latencies.record(20, None);
+        assert_eq!(store_latencies.receive_to_stored.lock().unwrap().len(), 2);
+        assert_eq!(store_latencies.eligible_to_stored.lock().unwrap().len(), 1);
+
+        store_latencies.log();
+        assert!(store_latencies.receive_to_stored.lock().unwrap().is_empty());
+        assert!(store_latencies
+            .eligible_to_stored
+            .lock()
+            .unwrap()
+            .is_empty());
     }
 }
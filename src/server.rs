@@ -2,32 +2,48 @@ use std::sync::Arc;
 
 use anyhow::Context;
 use axum::{routing::get, Router, Server};
+use fred::pool::RedisPool;
 use tokio::{sync::Notify, task::JoinHandle};
 use tracing::{error, info};
 
 use crate::{
+    api,
     env::{self, Env, ENV_CONFIG},
-    health::{self, RedisConsumerHealth, RedisHealth},
+    events::StoredEventsTx,
+    health::{self, RedisConsumerHealth, RedisHealth, TaskHealth},
+    metrics,
+    performance::{self, BlockCounter},
 };
 
 #[derive(Clone)]
 pub struct AppState {
+    pub block_counter: Arc<BlockCounter>,
+    pub consumer_task_health: TaskHealth,
     pub redis_health: RedisHealth,
     pub redis_consumer_health: RedisConsumerHealth,
+    pub redis_pool: RedisPool,
+    pub store_task_health: TaskHealth,
+    pub stored_events_tx: StoredEventsTx,
 }
 
-async fn serve(
-    redis_health: RedisHealth,
-    redis_consumer_health: RedisConsumerHealth,
-    shutdown_notify: Arc<Notify>,
-) {
-    let state = AppState {
-        redis_consumer_health,
-        redis_health,
-    };
+async fn serve(state: AppState, shutdown_notify: Arc<Notify>) {
     let result = {
         let app = Router::new()
+            .route("/events/stored", get(api::get_stored_events))
             .route("/livez", get(health::get_livez))
+            .route("/metrics", get(metrics::get_metrics))
+            .route("/payloads/keys/*key", get(api::get_payload_by_key))
+            .route(
+                "/payloads/:slot/:proposer_pubkey/:block_hash",
+                get(api::get_payload_by_parts),
+            )
+            .route("/readyz", get(health::get_readyz))
+            .route(
+                "/slots/:slot/best-bid/:parent_hash/:proposer_pubkey",
+                get(api::get_best_bid),
+            )
+            .route("/slots/:slot/bids", get(api::get_slot_bids))
+            .route("/stats", get(performance::get_stats))
             .with_state(state);
 
         let address = match ENV_CONFIG.env {
@@ -60,10 +76,6 @@ async fn serve(
     }
 }
 
-pub fn run_server_thread(
-    redis_health: RedisHealth,
-    redis_consumer_health: RedisConsumerHealth,
-    shutdown_notify: Arc<Notify>,
-) -> JoinHandle<()> {
-    tokio::spawn(serve(redis_health, redis_consumer_health, shutdown_notify))
+pub fn run_server_thread(state: AppState, shutdown_notify: Arc<Notify>) -> JoinHandle<()> {
+    tokio::spawn(serve(state, shutdown_notify))
 }
@@ -0,0 +1,150 @@
+//! # Slot clock
+//!
+//! Wall-clock slot timing for the configured network. Storage expiry, stale submission handling
+//! and health checks reason in slots, they should all get their timing from here.
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use lazy_static::lazy_static;
+
+use crate::{env::ENV_CONFIG, network::Network};
+
+lazy_static! {
+    pub static ref SLOT_CLOCK: SlotClock = SlotClock::new(&ENV_CONFIG.network);
+}
+
+/// Where the clock gets the current time from, so tests can control it.
+pub trait TimeSource: Send + Sync {
+    /// The time since the unix epoch.
+    fn now(&self) -> Duration;
+}
+
+pub struct SystemTimeSource;
+
+impl TimeSource for SystemTimeSource {
+    fn now(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("expect system time to be after the unix epoch")
+    }
+}
+
+pub struct SlotClock<T = SystemTimeSource> {
+    genesis_time: Duration,
+    slot_duration: Duration,
+    time_source: T,
+}
+
+impl SlotClock {
+    pub fn new(network: &Network) -> Self {
+        Self::with_time_source(network, SystemTimeSource)
+    }
+}
+
+impl<T: TimeSource> SlotClock<T> {
+    pub fn with_time_source(network: &Network, time_source: T) -> Self {
+        Self {
+            genesis_time: Duration::from_secs(network.genesis_time),
+            slot_duration: Duration::from_secs(network.seconds_per_slot),
+            time_source,
+        }
+    }
+
+    pub fn now(&self) -> Duration {
+        self.time_source.now()
+    }
+
+    /// The slot at the given time since the unix epoch, None before genesis.
+    pub fn slot_at(&self, time: Duration) -> Option<u64> {
+        time.checked_sub(self.genesis_time).map(|since_genesis| {
+            (since_genesis.as_millis() / self.slot_duration.as_millis()) as u64
+        })
+    }
+
+    /// The current slot, None before genesis.
+    pub fn current_slot(&self) -> Option<u64> {
+        self.slot_at(self.now())
+    }
+
+    /// When the slot starts, as time since the unix epoch.
+    pub fn slot_start(&self, slot: u64) -> Duration {
+        self.genesis_time + Duration::from_secs(self.slot_duration.as_secs() * slot)
+    }
+
+    /// When the slot ends, which is when the next slot starts.
+    pub fn slot_end(&self, slot: u64) -> Duration {
+        self.slot_start(slot + 1)
+    }
+
+    /// Time left until the next slot starts, or until genesis.
+    pub fn time_until_next_slot(&self) -> Duration {
+        let now = self.now();
+        match self.slot_at(now) {
+            Some(slot) => self.slot_end(slot) - now,
+            None => self.genesis_time - now,
+        }
+    }
+
+    pub fn slot_duration(&self) -> Duration {
+        self.slot_duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fork::ForkSchedule;
+
+    use super::*;
+
+    struct FixedTime(Duration);
+
+    impl TimeSource for FixedTime {
+        fn now(&self) -> Duration {
+            self.0
+        }
+    }
+
+    fn network() -> Network {
+        Network {
+            name: "testnet".to_string(),
+            genesis_time: 1000,
+            seconds_per_slot: 12,
+            fork_schedule: ForkSchedule {
+                capella_epoch: 0,
+                deneb_epoch: None,
+                electra_epoch: None,
+            },
+        }
+    }
+
+    fn clock_at(millis: u64) -> SlotClock<FixedTime> {
+        SlotClock::with_time_source(&network(), FixedTime(Duration::from_millis(millis)))
+    }
+
+    #[test]
+    fn current_slot_test() {
+        assert_eq!(clock_at(999_999).current_slot(), None);
+        assert_eq!(clock_at(1_000_000).current_slot(), Some(0));
+        assert_eq!(clock_at(1_011_999).current_slot(), Some(0));
+        assert_eq!(clock_at(1_012_000).current_slot(), Some(1));
+    }
+
+    #[test]
+    fn slot_start_and_end() {
+        let clock = clock_at(0);
+        assert_eq!(clock.slot_start(0), Duration::from_secs(1000));
+        assert_eq!(clock.slot_start(10), Duration::from_secs(1120));
+        assert_eq!(clock.slot_end(10), Duration::from_secs(1132));
+    }
+
+    #[test]
+    fn time_until_next_slot_test() {
+        assert_eq!(
+            clock_at(1_013_500).time_until_next_slot(),
+            Duration::from_millis(10_500)
+        );
+        assert_eq!(
+            clock_at(999_000).time_until_next_slot(),
+            Duration::from_millis(1_000)
+        );
+    }
+}
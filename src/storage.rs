@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use anyhow::Result;
 use fred::{
@@ -8,38 +8,207 @@ use fred::{
 };
 use futures::{channel::mpsc::Receiver, StreamExt, TryStreamExt};
 use tokio::{sync::Notify, task::JoinHandle};
-use tracing::{error, info};
+use tracing::{debug, error, info, warn};
 
-use crate::{performance::BlockCounter, BlockSubmission};
+use crate::{
+    archiver::{self, ArchiveTx},
+    best_bid,
+    bid_index::{self, Bid},
+    checkpoint::Checkpoint,
+    consumer::{group, StreamSubmission},
+    env::ENV_CONFIG,
+    events::{StoredEvent, StoredEventsTx},
+    health::{RedisConsumerHealth, TaskHealth},
+    metrics::{
+        self, CHANNEL_DEPTH, ELIGIBLE_TO_STORED, PAYLOADS_STORED, PAYLOAD_SIZE, RECEIVE_TO_STORED,
+        STORE_DURATION, SUBMISSIONS_SKIPPED,
+    },
+    performance::{BlockCounter, StoreLatencies},
+    slot_clock::{SlotClock, TimeSource, SLOT_CLOCK},
+    BlockSubmission,
+};
 
 const STORE_MAX_CONCURRENCY: usize = 4;
 
-// How long are the block submissions kept around in Redis. Given bidding starts before a slot, and
-// ends about 2 or 3 seconds into a slot anything over 2 slots in range i.e. 24 seconds, should be
-// safe. To be extra safe, we double that.
-const BLOCK_SUBMISSION_EXPIRATION_SECS: i64 = 48;
+// When a stored block submission expires. Bidding ends about 2 or 3 seconds into a slot, so
+// payloads are only useful until shortly after their slot ends. We keep them around for a
+// configurable number of slots after that, no matter when the bid arrived.
+pub(crate) fn expires_at<T: TimeSource>(
+    slot_clock: &SlotClock<T>,
+    slot: u64,
+    margin_slots: u64,
+) -> Duration {
+    slot_clock.slot_end(slot)
+        + Duration::from_secs(slot_clock.slot_duration().as_secs() * margin_slots)
+}
+
+fn record_latency(store_latencies: &StoreLatencies, block_submission: &BlockSubmission) {
+    let stored_at = SLOT_CLOCK.now().as_millis() as u64;
+    let receive_to_stored_ms = stored_at.saturating_sub(block_submission.received_at());
+    let eligible_to_stored_ms = block_submission
+        .eligible_at()
+        .map(|eligible_at| stored_at.saturating_sub(eligible_at));
+
+    RECEIVE_TO_STORED.observe(receive_to_stored_ms as f64 / 1000.0);
+    if let Some(eligible_to_stored_ms) = eligible_to_stored_ms {
+        ELIGIBLE_TO_STORED.observe(eligible_to_stored_ms as f64 / 1000.0);
+    }
+    store_latencies.record(receive_to_stored_ms, eligible_to_stored_ms);
+
+    if ENV_CONFIG
+        .latency_slo_ms
+        .is_some_and(|latency_slo_ms| receive_to_stored_ms > latency_slo_ms)
+    {
+        warn!(
+            key = %block_submission.block_submission_key(),
+            receive_to_stored_ms,
+            eligible_to_stored_ms,
+            "block submission took longer than the latency SLO to store"
+        );
+    }
+}
+
+/// What the store submissions thread shares with the rest of the service.
+pub struct StoreState {
+    pub archive_tx: Option<ArchiveTx>,
+    pub block_counter: Arc<BlockCounter>,
+    pub checkpoint: Checkpoint,
+    pub redis_consumer_health: RedisConsumerHealth,
+    pub redis_pool: RedisPool,
+    pub store_latencies: Arc<StoreLatencies>,
+    pub store_task_health: TaskHealth,
+    pub stored_events_tx: StoredEventsTx,
+}
 
 async fn store_submissions(
-    block_counter: &BlockCounter,
-    redis_pool: RedisPool,
-    submissions_rx: Receiver<BlockSubmission>,
+    state: &StoreState,
+    submissions_rx: Receiver<StreamSubmission>,
 ) -> Result<()> {
+    let StoreState {
+        archive_tx,
+        block_counter,
+        checkpoint,
+        redis_consumer_health,
+        redis_pool,
+        store_latencies,
+        stored_events_tx,
+        ..
+    } = state;
+
     submissions_rx
         .map(Ok)
-        .try_for_each_concurrent(STORE_MAX_CONCURRENCY, |block_submission| {
+        .try_for_each_concurrent(STORE_MAX_CONCURRENCY, |stream_submission| {
             let redis_pool = redis_pool.clone();
             async move {
-                redis_pool
-                    .set::<RedisValue, String, RedisValue>(
-                        block_submission.block_submission_key().to_string(),
-                        RedisValue::String(block_submission.execution_payload().to_string().into()),
-                        Some(Expiration::EX(BLOCK_SUBMISSION_EXPIRATION_SECS)),
-                        None,
-                        false,
-                    )
-                    .await?;
-
-                block_counter.increment();
+                CHANNEL_DEPTH.dec();
+
+                let StreamSubmission {
+                    id,
+                    stale,
+                    stream,
+                    submission: block_submission,
+                } = stream_submission;
+
+                let slot = block_submission.message().slot;
+                let expires_at = expires_at(&SLOT_CLOCK, slot, ENV_CONFIG.expiry_margin_slots);
+
+                // Stale submissions are only passed on to be archived. A late or replayed
+                // submission may already be past its expiry, storing it would be a no-op.
+                if stale {
+                    debug!(
+                        key = %block_submission.block_submission_key(),
+                        "block submission is stale, not storing"
+                    );
+                    SUBMISSIONS_SKIPPED
+                        .with_label_values(&[&stream, "stale"])
+                        .inc();
+                    block_counter.record_skipped(Some(slot));
+                } else if expires_at > SLOT_CLOCK.now() {
+                    let key = block_submission.block_submission_key().to_string();
+                    let payload = block_submission.stored_payload();
+                    PAYLOAD_SIZE.observe(payload.len() as f64);
+
+                    let bid = Bid::new(&block_submission, key);
+
+                    // The bid index and best bid go out in the same round trip as the payload.
+                    let timer = STORE_DURATION.start_timer();
+                    let pipeline = redis_pool.next().pipeline();
+                    pipeline
+                        .set::<(), _, _>(
+                            bid.key.as_str(),
+                            RedisValue::Bytes(payload),
+                            Some(Expiration::PXAT(expires_at.as_millis() as i64)),
+                            None,
+                            false,
+                        )
+                        .await?;
+                    bid_index::index_bid(&pipeline, &bid, expires_at).await?;
+                    best_bid::update_best_bid(&pipeline, &bid, expires_at).await?;
+                    let mut replies = pipeline.try_all::<RedisValue>().await.into_iter();
+                    replies
+                        .next()
+                        .transpose()
+                        .map_err(metrics::redis_error("set"))?;
+                    timer.observe_duration();
+                    record_latency(store_latencies, &block_submission);
+
+                    // The index and best bid are secondary, failing to update them is no reason to
+                    // stop storing. The best bid reply comes last, the index replies in between.
+                    if let Some(reply) = replies.next_back() {
+                        match best_bid::best_bid_from_reply(&redis_pool, reply, &bid, expires_at)
+                            .await
+                        {
+                            Ok(Some(best_bid)) => {
+                                debug!(
+                                    key = best_bid.key,
+                                    value = %best_bid.value,
+                                    "best bid changed"
+                                );
+                            }
+                            Ok(None) => (),
+                            Err(e) => warn!(key = bid.key, ?e, "failed to update best bid"),
+                        }
+                    }
+                    if let Some(e) = replies
+                        .find_map(|reply| reply.map_err(metrics::redis_error("index_bid")).err())
+                    {
+                        warn!(key = bid.key, ?e, "failed to index bid");
+                    }
+
+                    // Sending only fails when nobody is subscribed.
+                    let _ = stored_events_tx.send(StoredEvent {
+                        bid,
+                        eligible_at: block_submission.eligible_at(),
+                        stored_at: SLOT_CLOCK.now().as_millis() as u64,
+                    });
+
+                    PAYLOADS_STORED
+                        .with_label_values(&[&stream, &block_submission.fork().to_string()])
+                        .inc();
+                    block_counter.record_stored(slot);
+                } else {
+                    debug!(
+                        key = %block_submission.block_submission_key(),
+                        "block submission already expired, not storing"
+                    );
+                    SUBMISSIONS_SKIPPED
+                        .with_label_values(&[&stream, "expired"])
+                        .inc();
+                    block_counter.record_skipped(Some(slot));
+                }
+
+                // Whether stored or not, every submission we get is archived.
+                if let Some(archive_tx) = archive_tx {
+                    archiver::archive(archive_tx, block_submission);
+                }
+
+                // Only acknowledge once stored, until then the entry stays pending for the group.
+                if let Some(group) = ENV_CONFIG.consumer_group.as_deref() {
+                    group::ack(&redis_pool, &stream, group, vec![id.clone()]).await?;
+                }
+
+                checkpoint.mark_done(&stream, &id);
+                redis_consumer_health.set_last_processed_id(&stream, &id);
 
                 Ok(())
             }
@@ -48,15 +217,19 @@ async fn store_submissions(
 }
 
 pub fn run_store_submissions_thread(
-    block_counter: Arc<BlockCounter>,
-    redis_pool: RedisPool,
     shutdown_notify: Arc<Notify>,
-    submissions_rx: Receiver<BlockSubmission>,
+    state: StoreState,
+    submissions_rx: Receiver<StreamSubmission>,
 ) -> JoinHandle<()> {
     info!("starting store submissions thread");
     tokio::spawn({
         async move {
-            match store_submissions(&block_counter, redis_pool, submissions_rx).await {
+            let _running = state.store_task_health.start();
+            // Without the script cached, the first store loads it instead.
+            if let Err(e) = best_bid::load_update_script(&state.redis_pool).await {
+                warn!(?e, "failed to load update best bid script");
+            }
+            match store_submissions(&state, submissions_rx).await {
                 Ok(()) => {
                     info!("store submissions channel closed, store submissions thread exited");
                 }
@@ -68,3 +241,28 @@ pub fn run_store_submissions_thread(
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{fork::ForkSchedule, network::Network};
+
+    use super::*;
+
+    #[test]
+    fn expires_at_end_of_slot_plus_margin() {
+        let network = Network {
+            name: "testnet".to_string(),
+            genesis_time: 1000,
+            seconds_per_slot: 12,
+            fork_schedule: ForkSchedule {
+                capella_epoch: 0,
+                deneb_epoch: None,
+                electra_epoch: None,
+            },
+        };
+        let slot_clock = SlotClock::new(&network);
+
+        assert_eq!(expires_at(&slot_clock, 10, 0), Duration::from_secs(1132));
+        assert_eq!(expires_at(&slot_clock, 10, 2), Duration::from_secs(1156));
+    }
+}
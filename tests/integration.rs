@@ -2,8 +2,13 @@ use std::{sync::Arc, time::Duration};
 
 use anyhow::{Context, Result};
 use block_submission_service::{
-    env::ENV_CONFIG, run_consume_submissions_thread, run_store_submissions_thread, BlockSubmission,
-    JsonValue, RedisConsumerHealth, STREAM_NAME,
+    archiver::{self, ArchiveStore},
+    checkpoint::Checkpoint,
+    env::ENV_CONFIG,
+    fork::Fork,
+    run_consume_submissions_thread, run_store_submissions_thread,
+    slot_clock::SLOT_CLOCK,
+    BlockSubmission, JsonValue, RedisConsumerHealth, StoreState, TaskHealth, STREAM_NAME,
 };
 use fred::{
     pool::RedisPool,
@@ -11,8 +16,38 @@ use fred::{
     types::{MultipleOrderedPairs, RedisConfig, RedisValue},
 };
 use futures::channel::mpsc::channel;
+use object_store::{aws::AmazonS3Builder, path::Path as ObjectPath, Attribute};
+use serde_json::json;
 use tokio::{sync::Notify, time::sleep};
 
+const FIXTURE_PATH: &str = "tests/fixtures/0xffe314e3f12d726cf9f4a4babfcbfc836ef53d3144469f886423a833c853e3ef.json.gz.decompressed";
+
+// Our fixture is a Capella submission for a slot long gone, storing it would be a no-op as its
+// payload already expired. We move it to the current slot, with the fields its fork expects.
+fn current_slot_submission() -> Result<BlockSubmission> {
+    let file = std::fs::File::open(FIXTURE_PATH)?;
+    let mut submission: JsonValue = serde_json::from_reader(file)?;
+    let slot = SLOT_CLOCK
+        .current_slot()
+        .context("expect network to have started")?;
+
+    let payload = &mut submission["payload"];
+    payload["message"]["slot"] = json!(slot.to_string());
+    let fork = ENV_CONFIG.network.fork_schedule.fork_at_slot(slot);
+    if fork != Fork::Capella {
+        payload["execution_payload"]["blob_gas_used"] = json!("0");
+        payload["execution_payload"]["excess_blob_gas"] = json!("0");
+        payload["blobs_bundle"] = json!({ "commitments": [], "proofs": [], "blobs": [] });
+    }
+    if fork == Fork::Electra {
+        payload["execution_requests"] =
+            json!({ "deposits": [], "withdrawals": [], "consolidations": [] });
+    }
+
+    // Payloads keep their raw JSON, which only deserializes from text.
+    Ok(serde_json::from_str(&submission.to_string())?)
+}
+
 #[tokio::test]
 async fn store_block_submission() -> Result<()> {
     let shutdown_notify = Arc::new(Notify::new());
@@ -29,8 +64,12 @@ async fn store_block_submission() -> Result<()> {
     let redis_consumer_health = RedisConsumerHealth::new();
 
     let (submissions_tx, submissions_rx) = channel(4);
+    let checkpoint = Checkpoint::new();
 
     run_consume_submissions_thread(
+        block_counter.clone(),
+        checkpoint.clone(),
+        TaskHealth::new(),
         redis_consumer_health.clone(),
         redis_pool.clone(),
         shutdown_notify.clone(),
@@ -38,24 +77,29 @@ async fn store_block_submission() -> Result<()> {
     );
 
     run_store_submissions_thread(
-        block_counter,
-        redis_pool.clone(),
         shutdown_notify.clone(),
+        StoreState {
+            archive_tx: None,
+            block_counter,
+            checkpoint,
+            redis_consumer_health: redis_consumer_health.clone(),
+            redis_pool: redis_pool.clone(),
+            store_latencies: Arc::new(block_submission_service::performance::StoreLatencies::new()),
+            store_task_health: TaskHealth::new(),
+            stored_events_tx: block_submission_service::events::stored_events_channel(),
+        },
         submissions_rx,
     );
 
-    let block_submission = {
-        let file = std::fs::File::open("tests/fixtures/0xffe314e3f12d726cf9f4a4babfcbfc836ef53d3144469f886423a833c853e3ef.json.gz.decompressed")?;
-        let submission: BlockSubmission = serde_json::from_reader(file)?;
-        submission
-    };
+    let block_submission = current_slot_submission()?;
 
     let block_submission_key = block_submission.block_submission_key().to_string();
     let block_hash = block_submission.block_hash();
-    let pairs: MultipleOrderedPairs = block_submission.try_into()?;
+    let stored_payload: JsonValue = serde_json::from_slice(&block_submission.stored_payload())?;
+    let pairs: MultipleOrderedPairs = block_submission.into();
 
     redis_pool
-        .xadd(STREAM_NAME, false, None, "*", pairs)
+        .xadd::<(), _, _, _, _>(STREAM_NAME, false, None, "*", pairs)
         .await?;
 
     // Give our threads a moment to process the new block submission.
@@ -71,7 +115,62 @@ async fn store_block_submission() -> Result<()> {
     };
     let stored_submission: JsonValue = serde_json::from_str(&stored_submission)?;
 
-    assert_eq!(stored_submission["block_hash"], block_hash);
+    assert_eq!(stored_submission, stored_payload);
+    // From Deneb onwards the execution payload is stored next to the blobs bundle.
+    let execution_payload = stored_submission
+        .get("execution_payload")
+        .unwrap_or(&stored_submission);
+    assert_eq!(execution_payload["block_hash"], block_hash);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "requires MinIO, start it with docker compose up minio createbuckets"]
+async fn archive_to_minio() -> Result<()> {
+    let archive_store = ArchiveStore::s3(
+        AmazonS3Builder::new()
+            .with_endpoint(
+                std::env::var("S3_ENDPOINT").unwrap_or("http://localhost:9000".to_string()),
+            )
+            .with_allow_http(true)
+            .with_region("us-east-1")
+            .with_bucket_name("block-submission-archive-dev")
+            .with_access_key_id("minioadmin")
+            .with_secret_access_key("minioadmin"),
+    )?;
+
+    let block_submission: BlockSubmission = {
+        let file = std::fs::File::open(FIXTURE_PATH)?;
+        serde_json::from_reader(file)?
+    };
+    let block_hash = block_submission.block_hash();
+    let slot = block_submission.message().slot;
+
+    let key = archiver::object_key("testnet", slot, "integration", 0, 1);
+    let body = archiver::encode_batch(&[block_submission])?;
+    archive_store.put_object(&key, body.clone()).await?;
+
+    let object_store = archive_store.object_store();
+    let stored = object_store.get(&ObjectPath::from(key)).await?;
+    assert_eq!(
+        stored
+            .attributes
+            .get(&Attribute::ContentType)
+            .map(|value| value.as_ref()),
+        Some("application/gzip")
+    );
+    assert_eq!(stored.bytes().await?.as_ref(), body.as_slice());
+    assert!(matches!(
+        object_store
+            .get(&ObjectPath::from("testnet/0/missing.ndjson.gz"))
+            .await,
+        Err(object_store::Error::NotFound { .. })
+    ));
+
+    let decoded: JsonValue =
+        serde_json::from_reader(flate2::read::GzDecoder::new(body.as_slice()))?;
+    assert_eq!(decoded["payload"]["message"]["block_hash"], block_hash);
 
     Ok(())
 }